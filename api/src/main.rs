@@ -1,32 +1,140 @@
 use axum::{
-    http::{header::{CONTENT_TYPE, USER_AGENT}, Method, StatusCode},
-    response::Html,
+    extract::{Path, Query},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
+        HeaderMap, Method, StatusCode,
+    },
+    response::{Html, IntoResponse, Response},
     routing::{delete, get, patch, post},
     Json, Router,
 };
 use dotenv::dotenv;
+use model::adversarial::{self, Adversary, Attack};
+use model::calibration;
+use model::classifier::{self, Classifier, ClassifierKind, FitOptions};
+use model::dataset::Dataset;
+use model::distill::Distillation;
+use model::expression;
+use model::federated::{self, Aggregation, Aggregator, Rejection};
+use model::history::TrainingHistory;
+use model::knn::Knn;
+use model::logistic::LogisticRegression;
+use model::ood::{OodDetector, OodMethod};
+use model::optimizer::{Optimizer, Trainer};
+use model::preprocess;
+use model::prune;
+use model::replay::{self, Ewc, ReplayBuffer};
+use model::search::{self, Config, SearchSpace, Strategy};
+use model::segment;
+use model::sparse::SparseWeights;
 use model::util;
-use model::util::{get_sample_block, train_handler_wrapper, Data, DataInfo, DataSingle, Weights};
+use model::util::{
+    get_sample_block, get_sample_block_with, train_handler, ClassifierInfo, Data, DataInfo,
+    DataSingle, DistillInfo, EnsembleInfo, ImagesInfo, ModelCard, NumberInfo, PruneInfo,
+    RobustnessInfo, VaeInfo, WeightUpdate, Weights,
+};
+use model::vae::{Vae, VaeWeights};
+use model::Ensemble;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::fs::File;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use tower_http::cors::{Any, CorsLayer};
 
+#[derive(Default)]
+struct Pending {
+    // the version the open round trains against, pinned by its first update
+    base: Option<u64>,
+    updates: Vec<WeightUpdate>,
+    started: Option<Instant>,
+    rejected: Vec<Rejection>,
+    // loaded once at startup rather than read for every round
+    holdout: Option<Arc<Holdout>>,
+}
+
+type Holdout = (Vec<Vec<f64>>, Vec<u8>);
+
+// the updates of a closed round, aggregated without holding the lock on Pending
+struct Round {
+    base: u64,
+    updates: Vec<WeightUpdate>,
+    holdout: Option<Arc<Holdout>>,
+}
+
+const MAX_REJECTIONS: usize = 100;
+const REPLAY_BATCH: usize = 32;
+
+// the validation split, kept from the first time the training data is read
+static VALIDATION_DATA: OnceLock<Data> = OnceLock::new();
+
+// examples mixed into PATCH /weights, anchored to the weights the buffer was seeded for
+#[derive(Default)]
+struct Replay {
+    buffer: ReplayBuffer,
+    ewc: Option<Ewc>,
+    version: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ReplayQuery {
+    replay: Option<usize>,
+    ewc: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct CalibrationQuery {
+    bins: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct OodQuery {
+    method: Option<String>,
+    accept: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    format: Option<String>,
+}
+
 #[tokio::main]
 async fn main() {
     dotenv().ok();
     check_envs();
+    if std::env::args().nth(1).as_deref() == Some("search") {
+        hyperparameter_search();
+        return;
+    }
     let shared_data = Arc::new(Mutex::new(Data { data: Vec::new() }));
     let weights = get_env("WEIGHTS");
     let bind_url = get_env("BIND_URL");
 
     if File::open(weights).is_err() {
-        output_filter(format!("Creating weights file"), 0);
+        output_filter("Creating weights file".to_string(), 0);
         let _ = weights_delete(shared_data.clone()).await;
     }
+    if list_versions().is_empty() {
+        save_version(&get_weights());
+    }
     let weights_delete_data = shared_data.clone();
     let sample_data_data = shared_data.clone();
     let sample_block_data = shared_data.clone();
+    let weights_distill_data = shared_data.clone();
+    let weights_prune_data = shared_data.clone();
+    let weights_patch_data = shared_data.clone();
+    let vae_post_data = shared_data.clone();
+    let classifiers_data = shared_data.clone();
+    let replay = Arc::new(Mutex::new(Replay::default()));
+    let pending = Arc::new(Mutex::new(Pending {
+        holdout: load_holdout().map(Arc::new),
+        ..Default::default()
+    }));
+    let federated_get_pending = pending.clone();
+    let federated_post_pending = pending.clone();
+    tokio::spawn(federated_timeout(pending));
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
         .allow_headers([USER_AGENT, CONTENT_TYPE])
@@ -42,7 +150,48 @@ async fn main() {
                 )
                 .route("/weights", get(weights_get))
                 .route("/weights", post(weights_post))
-                .route("/weights", patch(weights_patch))
+                .route(
+                    "/weights",
+                    patch(move |query, args| {
+                        weights_patch(query, args, replay, weights_patch_data)
+                    }),
+                )
+                .route("/weights/sparse", get(weights_sparse))
+                .route("/weights/metadata", get(metadata_get))
+                .route("/weights/versions", get(versions_get))
+                .route("/weights/versions/:version", get(version_get))
+                .route("/weights/versions/:version/history", get(history_get))
+                .route("/ensemble", post(ensemble_infer))
+                .route("/robustness", post(robustness_post))
+                .route("/activations", post(activations_post))
+                .route("/embed", post(embed_post))
+                .route("/predict", post(predict_post))
+                .route("/number", post(number_post))
+                .route("/expression", post(expression_post))
+                .route("/calibrate", post(calibrate_post))
+                .route("/ood", post(ood_post))
+                .route(
+                    "/federated",
+                    get(move || federated_get(federated_get_pending)),
+                )
+                .route(
+                    "/federated/updates",
+                    post(move |args| federated_post(args, federated_post_pending)),
+                )
+                .route(
+                    "/weights/prune",
+                    post(move |args| weights_prune(args, weights_prune_data)),
+                )
+                .route(
+                    "/weights/distill",
+                    post(move |args| weights_distill(args, weights_distill_data)),
+                )
+                .route("/vae", get(vae_get))
+                .route("/vae", post(move |args| vae_post(args, vae_post_data)))
+                .route(
+                    "/classifiers",
+                    post(move |args| classifiers_post(args, classifiers_data)),
+                )
                 .route("/data", get(move || sample_data(sample_data_data)))
                 .route(
                     "/datablock",
@@ -64,18 +213,538 @@ async fn weights_get() -> Json<Value> {
     Json(json!(weights))
 }
 
-async fn weights_post(Json(weights): Json<Weights>) -> StatusCode {
+// replaces the weights outright, which only operators holding ADMIN_TOKEN may do
+async fn weights_post(headers: HeaderMap, Json(weights): Json<Weights>) -> StatusCode {
+    let token = get_env_opt("ADMIN_TOKEN").map(|x| format!("Bearer {}", x));
+    let auth = headers.get(AUTHORIZATION).and_then(|x| x.to_str().ok());
+    if token.is_none() || auth != token.as_deref() {
+        return StatusCode::UNAUTHORIZED;
+    }
+    if let Err(err) = weights.check() {
+        output_filter(format!("Rejected uploaded weights: {}", err), 1);
+        return StatusCode::BAD_REQUEST;
+    }
     write_weights(&weights);
+    save_version(&weights);
     StatusCode::OK
 }
 
+async fn versions_get() -> Json<Value> {
+    Json(json!(list_versions()))
+}
+
+async fn version_get(Path(version): Path<u64>) -> Result<Json<Value>, StatusCode> {
+    match get_version(version) {
+        Some(weights) => Ok(Json(json!(weights))),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn metadata_get() -> Json<Value> {
+    let weights = get_weights();
+    let card = match weights.card {
+        Some(card) => card,
+        None => ModelCard::from_model(&model::Model::from_weights(weights.clone(), (0.0, 0.0))),
+    };
+    Json(json!({
+        "card": card,
+        "classes": weights.classes,
+    }))
+}
+
+async fn history_get(
+    Path(version): Path<u64>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Response, StatusCode> {
+    let history = get_history(version).ok_or(StatusCode::NOT_FOUND)?;
+    let csv = |body: String| ([(CONTENT_TYPE, "text/csv")], body).into_response();
+    match query.format.as_deref() {
+        None | Some("json") => Ok(Json(json!(history)).into_response()),
+        Some("csv") => Ok(csv(history.steps_csv())),
+        Some("epochs_csv") => Ok(csv(history.epochs_csv())),
+        Some(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn ensemble_infer(Json(args): Json<EnsembleInfo>) -> Result<Json<Value>, StatusCode> {
+    let weights = args
+        .versions
+        .iter()
+        .map(|x| get_version(*x))
+        .collect::<Option<Vec<Weights>>>()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let size = args.images.first().ok_or(StatusCode::BAD_REQUEST)?.len();
+    if weights.is_empty()
+        || weights.iter().any(|x| x.check_input_size(size).is_err())
+        || args.images.iter().any(|x| x.len() != size)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let members = weights
+        .into_iter()
+        .map(|x| model::Model::from_weights(x, (0.0, 0.0)))
+        .collect::<Vec<model::Model>>();
+    let member_weights = match args.member_weights.len() {
+        0 => vec![1.0; members.len()],
+        _ => args.member_weights,
+    };
+    // a negative or nan weight, or weights summing to zero, leave nothing to normalise by
+    if member_weights.len() != members.len()
+        || member_weights.iter().any(|x| x.is_nan() || *x < 0.0)
+        || member_weights.iter().sum::<f64>() <= 0.0
+        || members.iter().any(|x| {
+            x.num_classes() != members[0].num_classes() || x.input_size() != members[0].input_size()
+        })
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let ensemble = Ensemble::with_weights(members, member_weights, args.combine);
+    let predictions = ensemble.infer2d(args.images);
+    let labels = predictions
+        .iter()
+        .map(|x| ensemble.class_name(*x))
+        .collect::<Vec<String>>();
+    Ok(Json(
+        json!({ "predictions": predictions, "labels": labels }),
+    ))
+}
+
+async fn activations_post(Json(args): Json<ImagesInfo>) -> Result<Json<Value>, StatusCode> {
+    let model = images_model(&args)?;
+    Ok(Json(json!(model.activations2d(args.images))))
+}
+
+// predictions with probabilities softened by the temperature in the model card
+async fn predict_post(Json(args): Json<ImagesInfo>) -> Result<Json<Value>, StatusCode> {
+    let model = images_model(&args)?;
+    let weights = get_weights();
+    let recognised = match get_detector(&weights) {
+        Some(ood) => ood.recognised(&model, args.images.clone()),
+        None => vec![true; args.images.len()],
+    };
+    let probabilities = calibration::probabilities(&model, args.images, weights.temperature());
+    Ok(Json(json!(probabilities
+        .into_iter()
+        .zip(recognised)
+        .map(|(x, recognised)| {
+            let prediction = util::argmax(x.iter());
+            json!({
+                "prediction": prediction,
+                "class": match recognised {
+                    true => model.class_name(prediction),
+                    false => "unrecognised".to_string(),
+                },
+                "recognised": recognised,
+                "confidence": x[prediction as usize],
+                "probabilities": x,
+            })
+        })
+        .collect::<Vec<Value>>())))
+}
+
+// reads every digit drawn side by side on a wider canvas
+async fn number_post(Json(args): Json<NumberInfo>) -> Result<Json<Value>, StatusCode> {
+    if args.width == 0 || args.image.is_empty() || args.image.len() % args.width != 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let weights = get_weights();
+    weights
+        .check_input_size(util::IMAGE_SIZE)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let model = model::Model::from_weights(weights.clone(), (0.0, 0.0));
+    let reading = segment::read(
+        &model,
+        &args.image,
+        args.width,
+        args.segmentation,
+        weights.temperature(),
+        get_detector(&weights).as_ref(),
+    );
+    Ok(Json(json!(reading)))
+}
+
+// reads a handwritten sum like 12+7×3 and evaluates it
+async fn expression_post(Json(args): Json<NumberInfo>) -> Result<Json<Value>, StatusCode> {
+    if args.width == 0 || args.image.is_empty() || args.image.len() % args.width != 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let weights = get_weights();
+    weights
+        .check_input_size(util::IMAGE_SIZE)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let model = model::Model::from_weights(weights.clone(), (0.0, 0.0));
+    let reading = expression::read(
+        &model,
+        &args.image,
+        args.width,
+        args.segmentation,
+        weights.temperature(),
+        get_detector(&weights).as_ref(),
+    );
+    Ok(Json(json!(reading)))
+}
+
+// refits the temperature of the served weights on the validation split
+async fn calibrate_post(Query(query): Query<CalibrationQuery>) -> Result<Json<Value>, StatusCode> {
+    let bins = query.bins.unwrap_or(10);
+    if bins == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let weights = get_weights();
+    let model = model::Model::from_weights(weights.clone(), (0.0, 0.0));
+    let (images, targets) = util::split_chunk(&validation_data().data);
+    let report = calibration::calibrate(&model, images, &targets, bins);
+    let card = ModelCard {
+        temperature: Some(report.temperature),
+        ..weights
+            .card
+            .unwrap_or_else(|| ModelCard::from_model(&model))
+    };
+    let version = publish_weights(&model, card, None);
+    Ok(Json(json!({ "version": version, "calibration": report })))
+}
+
+// refits the out of distribution detector of the served weights on the validation split
+async fn ood_post(Query(query): Query<OodQuery>) -> Result<Json<Value>, StatusCode> {
+    if query.accept.is_some_and(|x| !(x > 0.0 && x <= 1.0)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let weights = get_weights();
+    let method = match query.method {
+        Some(name) => Some(OodMethod::from_name(&name).ok_or(StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    let model = model::Model::from_weights(weights.clone(), (0.0, 0.0));
+    let (images, targets) = util::split_chunk(&validation_data().data);
+    let ood = fit_ood(
+        &model,
+        images,
+        &targets,
+        weights.temperature(),
+        method,
+        query.accept,
+    );
+    let card = ModelCard {
+        ood: Some(ood.clone()),
+        ..weights
+            .card
+            .unwrap_or_else(|| ModelCard::from_model(&model))
+    };
+    let version = publish_weights(&model, card, None);
+    Ok(Json(json!({
+        "version": version,
+        "method": ood.method,
+        "threshold": ood.threshold,
+    })))
+}
+
+async fn embed_post(Json(args): Json<ImagesInfo>) -> Result<Json<Value>, StatusCode> {
+    let model = images_model(&args)?;
+    Ok(Json(json!(model.embed2d(args.images))))
+}
+
+// the served model, if the images fit its input
+fn images_model(args: &ImagesInfo) -> Result<model::Model, StatusCode> {
+    let weights = get_weights();
+    let size = args.images.first().ok_or(StatusCode::BAD_REQUEST)?.len();
+    if weights.check_input_size(size).is_err() || args.images.iter().any(|x| x.len() != size) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(model::Model::from_weights(weights, (0.0, 0.0)))
+}
+
+async fn robustness_post(Json(args): Json<RobustnessInfo>) -> Result<Json<Value>, StatusCode> {
+    if args.samples == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let model = model::Model::from_weights(get_weights(), (0.0, 0.0));
+    let (images, targets) = util::split_chunk(&get_sample_block(test_data(), args.samples));
+    output_filter(
+        format!("Attacking {} samples with {:?}", targets.len(), args.attack),
+        1,
+    );
+    let report = adversarial::robustness(
+        &model,
+        images,
+        &targets,
+        args.attack,
+        &args.epsilons,
+        args.steps,
+    );
+    Ok(Json(json!({
+        "attack": args.attack,
+        "report": report,
+    })))
+}
+
+async fn federated_get(pending: Arc<Mutex<Pending>>) -> Json<Value> {
+    let pending = pending.lock().unwrap();
+    Json(json!({
+        "version": federated_base(&pending),
+        "pending": pending.updates.len(),
+        "quorum": get_quorum(),
+        "aggregation": get_aggregator().method,
+        "rejected": pending.rejected,
+    }))
+}
+
+async fn federated_post(
+    Json(update): Json<WeightUpdate>,
+    pending: Arc<Mutex<Pending>>,
+) -> Result<Json<Value>, StatusCode> {
+    // the sample count weights the average, so no client may claim more than the server allows
+    if update.samples > get_max_samples() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let base = federated_base(&pending.lock().unwrap()).ok_or(StatusCode::CONFLICT)?;
+    if update.base_version != base {
+        return Err(StatusCode::CONFLICT);
+    }
+    let weights = get_version(base).ok_or(StatusCode::CONFLICT)?;
+    let model = model::Model::from_weights(weights, (0.0, 0.0));
+    if !federated::matches_model(&model, &update) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let (queued, round) = {
+        // the base is checked again under the lock in case the round moved on meanwhile
+        let mut pending = pending.lock().unwrap();
+        if federated_base(&pending) != Some(base) {
+            return Err(StatusCode::CONFLICT);
+        }
+        pending.base = Some(base);
+        pending.updates.push(update);
+        pending.started.get_or_insert_with(Instant::now);
+        let queued = pending.updates.len();
+        output_filter(
+            format!("Received update {} for version {}", queued, base),
+            1,
+        );
+        let round = match queued >= get_quorum() {
+            true => close_round(&mut pending),
+            false => None,
+        };
+        (queued, round)
+    };
+    let version = match round {
+        Some(round) => finish_round(&pending, round).await,
+        None => None,
+    };
+    Ok(Json(json!({
+        "pending": queued,
+        "version": version,
+    })))
+}
+
+async fn federated_timeout(pending: Arc<Mutex<Pending>>) {
+    let timeout =
+        Duration::from_secs(get_env_opt("FED_TIMEOUT").map_or(300, |x| x.parse::<u64>().unwrap()));
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let round = {
+            let mut pending = pending.lock().unwrap();
+            match pending.started.is_some_and(|x| x.elapsed() >= timeout) {
+                true => close_round(&mut pending),
+                false => None,
+            }
+        };
+        if let Some(round) = round {
+            finish_round(&pending, round).await;
+        }
+    }
+}
+
+// Takes the updates of the open round, so the next round can start while they are aggregated
+fn close_round(pending: &mut Pending) -> Option<Round> {
+    let updates = std::mem::take(&mut pending.updates);
+    pending.started = None;
+    Some(Round {
+        base: pending.base.take()?,
+        updates,
+        holdout: pending.holdout.clone(),
+    })
+}
+
+// Aggregates a closed round on a blocking thread and records what it rejected
+async fn finish_round(pending: &Arc<Mutex<Pending>>, round: Round) -> Option<u64> {
+    let (version, rejected) = tokio::task::spawn_blocking(move || aggregate(round))
+        .await
+        .unwrap();
+    record_rejections(&mut pending.lock().unwrap(), rejected);
+    version
+}
+
+// Aggregate the updates of the round into a new version, along with the updates it rejected
+fn aggregate(round: Round) -> (Option<u64>, Vec<Rejection>) {
+    let Round {
+        base,
+        updates,
+        holdout,
+    } = round;
+    let reject_all = |updates: Vec<WeightUpdate>, reason: String| {
+        updates
+            .into_iter()
+            .map(|x| rejection(x, 0.0, None, reason.clone()))
+            .collect()
+    };
+    let Some(weights) = get_version(base) else {
+        let reason = format!("base version {} is no longer stored", base);
+        return (None, reject_all(updates, reason));
+    };
+    let base_model = model::Model::from_weights(weights, (0.0, 0.0));
+    let (updates, rejected) = screen_updates(&base_model, updates, holdout.as_deref());
+    if updates.is_empty() {
+        return (None, rejected);
+    }
+    // the deltas are rebased onto the served weights, which may have moved on during the round
+    let served = get_weights();
+    let mut model = model::Model::from_weights(served.clone(), (0.0, 0.0));
+    if model.weights.0.dim() != base_model.weights.0.dim()
+        || model.weights.1.dim() != base_model.weights.1.dim()
+    {
+        let reason = format!("the served weights changed shape since version {}", base);
+        let mut rejected = rejected;
+        rejected.extend(reject_all(updates, reason));
+        return (None, rejected);
+    }
+    federated::apply_delta(&mut model, get_aggregator().aggregate(&updates));
+    let mut card = inherited_card(&model, &served);
+    card.hyperparameters
+        .insert("federated_updates".to_string(), updates.len() as f64);
+    let version = publish_weights(&model, card, None);
+    output_filter(
+        format!(
+            "Aggregated {} updates on version {} into version {}",
+            updates.len(),
+            base,
+            version
+        ),
+        0,
+    );
+    (Some(version), rejected)
+}
+
+// Rejects updates far from the consensus of the others or that hurt held-out accuracy
+fn screen_updates(
+    base: &model::Model,
+    updates: Vec<WeightUpdate>,
+    holdout: Option<&Holdout>,
+) -> (Vec<WeightUpdate>, Vec<Rejection>) {
+    // a consensus needs a majority of honest updates to mean anything
+    let scores = match updates.len() >= 3 {
+        true => federated::consensus_scores(&updates),
+        false => vec![0.0; updates.len()],
+    };
+    let max_score = get_env_opt("FED_MAX_SCORE").map_or(3.0, |x| x.parse::<f64>().unwrap());
+    let max_drop = get_env_opt("FED_MAX_ACCURACY_DROP").map_or(0.05, |x| x.parse::<f64>().unwrap());
+    let holdout = holdout.map(|holdout| {
+        let accuracy = federated::accuracy(base, holdout.0.clone(), &holdout.1);
+        (holdout, accuracy)
+    });
+    let mut accepted = Vec::new();
+    let mut rejected = Vec::new();
+    for (update, score) in updates.into_iter().zip(scores) {
+        let accuracy_drop = holdout.as_ref().map(|(holdout, accuracy)| {
+            federated::accuracy_drop(base, *accuracy, &update, &holdout.0, &holdout.1)
+        });
+        let reason = match accuracy_drop {
+            _ if score > max_score => format!(
+                "distance from the consensus is {:.2} times the median",
+                score
+            ),
+            Some(drop) if drop > max_drop => {
+                format!("held-out accuracy drops by {:.4}", drop)
+            }
+            _ => {
+                accepted.push(update);
+                continue;
+            }
+        };
+        rejected.push(rejection(update, score, accuracy_drop, reason));
+    }
+    (accepted, rejected)
+}
+
+fn rejection(
+    update: WeightUpdate,
+    score: f64,
+    accuracy_drop: Option<f64>,
+    reason: String,
+) -> Rejection {
+    output_filter(format!("Rejected update: {}", reason), 0);
+    Rejection {
+        base_version: update.base_version,
+        samples: update.samples,
+        score,
+        accuracy_drop,
+        reason,
+    }
+}
+
+fn record_rejections(pending: &mut Pending, rejected: Vec<Rejection>) {
+    pending.rejected.extend(rejected);
+    let excess = pending.rejected.len().saturating_sub(MAX_REJECTIONS);
+    pending.rejected.drain(..excess);
+}
+
+fn load_holdout() -> Option<Holdout> {
+    let size = get_env_opt("FED_HOLDOUT").map_or(0, |x| x.parse::<usize>().unwrap());
+    if size == 0 {
+        return None;
+    }
+    output_filter("Loading held-out data".to_string(), 1);
+    let holdout = get_sample_block(test_data(), size);
+    Some(util::split_chunk(&holdout))
+}
+
+fn get_adversary() -> Option<Adversary> {
+    let epsilon = get_env_opt("ADVERSARIAL_EPSILON")?.parse::<f64>().unwrap();
+    let attack = match get_env_opt("ADVERSARIAL_ATTACK") {
+        Some(name) => Attack::from_name(&name)
+            .unwrap_or_else(|| panic!("ADVERSARIAL_ATTACK {} is not supported", name)),
+        None => Attack::default(),
+    };
+    output_filter(
+        format!("Training adversarially with {:?} at {}", attack, epsilon),
+        0,
+    );
+    Some(Adversary::new(attack, epsilon))
+}
+
+fn get_aggregator() -> Aggregator {
+    let default = Aggregator::default();
+    Aggregator {
+        method: match get_env_opt("FED_AGGREGATION") {
+            Some(name) => Aggregation::from_name(&name)
+                .unwrap_or_else(|| panic!("FED_AGGREGATION {} is not supported", name)),
+            None => default.method,
+        },
+        trim: get_env_opt("FED_TRIM").map_or(default.trim, |x| x.parse::<f64>().unwrap()),
+        byzantine: get_env_opt("FED_BYZANTINE")
+            .map_or(default.byzantine, |x| x.parse::<usize>().unwrap()),
+        clip_norm: get_env_opt("FED_CLIP_NORM").map(|x| x.parse::<f64>().unwrap()),
+    }
+}
+
+// Clients train against the version of the open round, or the latest one between rounds
+fn federated_base(pending: &Pending) -> Option<u64> {
+    pending.base.or_else(|| list_versions().last().copied())
+}
+
+fn get_max_samples() -> usize {
+    get_env_opt("FED_MAX_SAMPLES").map_or(60000, |x| x.parse::<usize>().unwrap())
+}
+
+fn get_quorum() -> usize {
+    get_env_opt("FED_QUORUM").map_or(5, |x| x.parse::<usize>().unwrap())
+}
+
 async fn sample_data(data: Arc<Mutex<Data>>) -> Json<Value> {
     data_refresh(data.clone());
     let data = data.lock().unwrap();
     let sample = Data {
         data: get_sample_block(&data, 1),
     };
-    Json(json!(sample)).into()
+    Json(json!(sample))
 }
 
 async fn sample_data_block(Json(args): Json<DataInfo>, data: Arc<Mutex<Data>>) -> Json<Value> {
@@ -84,17 +753,52 @@ async fn sample_data_block(Json(args): Json<DataInfo>, data: Arc<Mutex<Data>>) -
     let sample = Data {
         data: get_sample_block(&data, args.block),
     };
-    Json(json!(sample)).into()
+    Json(json!(sample))
 }
 
-async fn weights_patch(Json(data): Json<Data>) -> Json<Value> {
+async fn weights_patch(
+    Query(query): Query<ReplayQuery>,
+    Json(data): Json<Data>,
+    replay: Arc<Mutex<Replay>>,
+    train: Arc<Mutex<Data>>,
+) -> Json<Value> {
+    let data = preprocess_data(data);
     let lrate = get_env("LEARNING_RATE").parse::<f64>().unwrap();
     let weights: Weights = get_weights();
-    let mut model = model::Model::new(weights.weights, (lrate, lrate));
+    let mut model = model::Model::from_weights(weights.clone(), (lrate, lrate));
+    let size = query
+        .replay
+        .or_else(|| get_env_opt("REPLAY_SIZE").map(|x| x.parse::<usize>().unwrap()));
+    if let (Some(size), false) = (size.filter(|x| *x > 0), data.data.is_empty()) {
+        let lambda = query
+            .ewc
+            .or_else(|| get_env_opt("EWC_LAMBDA").map(|x| x.parse::<f64>().unwrap()));
+        let mut replay = replay.lock().unwrap();
+        seed_replay(&mut replay, &model, size, lambda, train);
+        let count = get_env_opt("REPLAY_BATCH")
+            .map(|x| x.parse::<usize>().unwrap())
+            .unwrap_or(REPLAY_BATCH);
+        let mut rng = get_rng();
+        let Replay { buffer, ewc, .. } = &mut *replay;
+        let res = replay::train_online(
+            &mut model,
+            buffer,
+            &data.data,
+            count,
+            ewc.as_ref(),
+            &mut rng,
+        );
+        replay.version = Some(publish_weights(
+            &model,
+            inherited_card(&model, &weights),
+            None,
+        ));
+        return Json(json!({ "loss": res, "replay": replay.buffer.len() }));
+    }
     match data.data.len() {
         0 => Json(json!({"loss": 0})),
         1 => {
-            let res = model.train1d(data.data[0].image.clone(), data.data[0].target.into());
+            let res = model.train1d(data.data[0].image.clone(), data.data[0].target);
             Json(json!({ "loss": res }))
         }
         _ => {
@@ -104,33 +808,399 @@ async fn weights_patch(Json(data): Json<Data>) -> Json<Value> {
                     .into_iter()
                     .map(|x| x.image.clone())
                     .collect(),
-                data.data.into_iter().map(|x| x.target as u8).collect(),
+                data.data.into_iter().map(|x| x.target).collect(),
             );
-            sync_weights(&model);
+            publish_weights(&model, inherited_card(&model, &weights), None);
             Json(json!({ "loss": res }))
         }
     }
 }
 
-async fn weights_delete(data: Arc<Mutex<Data>>) -> Html<&'static str> {
+// fills the buffer with a reservoir sample of the training data whenever the served weights
+// were replaced by something other than an earlier patch, and re-anchors the penalty there
+fn seed_replay(
+    replay: &mut Replay,
+    model: &model::Model,
+    size: usize,
+    lambda: Option<f64>,
+    train: Arc<Mutex<Data>>,
+) {
+    if replay.version.is_none() || replay.version != list_versions().last().copied() {
+        data_refresh(train.clone());
+        let mut rng = get_rng();
+        replay.buffer = ReplayBuffer::new(size);
+        replay
+            .buffer
+            .extend_with(&train.lock().unwrap().data, &mut rng);
+        replay.ewc = None;
+        output_filter(
+            format!("Seeded replay buffer with {} examples", replay.buffer.len()),
+            1,
+        );
+    }
+    if replay.buffer.capacity != size {
+        replay.buffer.set_capacity(size);
+    }
+    replay.ewc = match (lambda.filter(|x| *x > 0.0), replay.ewc.take()) {
+        (Some(lambda), Some(ewc)) => Some(Ewc { lambda, ..ewc }),
+        (Some(lambda), None) => Some(Ewc::new(model, &replay.buffer.examples, lambda)),
+        (None, _) => None,
+    };
+}
+
+async fn weights_delete(data: Arc<Mutex<Data>>) -> Result<Html<&'static str>, StatusCode> {
     data_refresh(data.clone());
 
     let lrate = get_env("LEARNING_RATE").parse::<f64>().unwrap();
     let batch_size = get_env("BATCH_SIZE").parse::<usize>().unwrap();
+
     let iters = get_env("TRAIN_ITER").parse::<usize>().unwrap();
 
-    let mut model = model::Model::new(
-        (util::random_dist(784, 128), util::random_dist(128, 10)),
+    let mut rng = get_rng();
+    let mut model = new_model(&data, util::HIDDEN_SIZE, lrate, &mut rng)?;
+    let adversary = get_adversary();
+
+    let history = train_iters(&data, &mut rng, iters, |chunk, iter| {
+        let res = match &adversary {
+            Some(adversary) => adversary.train_handler(chunk, &mut model, batch_size),
+            None => train_handler(chunk, &mut model, batch_size),
+        };
+        if iter % 500 == 0 {
+            output_filter(format!("Iter {} - Syncing weights", iter), 0);
+            sync_weights(&model);
+        }
+        res
+    });
+    let accuracy = get_accuracy(&model);
+    let mut hyperparameters = vec![
+        ("learning_rate", lrate),
+        ("batch_size", batch_size as f64),
+        ("iterations", iters as f64),
+        ("hidden", util::HIDDEN_SIZE as f64),
+    ];
+    let mut settings = Vec::new();
+    if let Some(adversary) = adversary {
+        hyperparameters.push(("adversarial_epsilon", adversary.epsilon));
+        settings.push(("adversarial_attack", adversary.attack.name()));
+    }
+    let (temperature, ood) = fit_validation(&model);
+    let card = ModelCard {
+        temperature: Some(temperature),
+        ood: Some(ood),
+        ..make_card(&model, &data, &hyperparameters, &settings, Some(accuracy))
+    };
+    publish_weights(&model, card, Some(&history));
+    output_filter(format!("Final Accuracy: {}", accuracy), 0);
+
+    Ok(Html("Done"))
+}
+
+async fn weights_distill(
+    Json(args): Json<DistillInfo>,
+    data: Arc<Mutex<Data>>,
+) -> Result<Html<&'static str>, StatusCode> {
+    // a temperature at or below zero turns the soft targets into nan
+    if args.temperature.is_nan()
+        || args.temperature <= 0.0
+        || !(0.0..=1.0).contains(&args.alpha)
+        || args.hidden == 0
+        || args.teacher_hidden == 0
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    data_refresh(data.clone());
+
+    let lrate = get_env("LEARNING_RATE").parse::<f64>().unwrap();
+    let batch_size = get_env("BATCH_SIZE").parse::<usize>().unwrap();
+    let iters = get_env("TRAIN_ITER").parse::<usize>().unwrap();
+    let distillation = Distillation::new(args.temperature, args.alpha);
+
+    let mut rng = get_rng();
+    let mut teacher = new_model(&data, args.teacher_hidden, lrate, &mut rng)?;
+    output_filter(
+        format!("Training teacher with {} hidden units", args.teacher_hidden),
+        0,
+    );
+    train_iters(&data, &mut rng, iters, |chunk, _| {
+        train_handler(chunk, &mut teacher, batch_size)
+    });
+    output_filter(format!("Teacher Accuracy: {}", get_accuracy(&teacher)), 0);
+
+    let mut student = new_model(&data, args.hidden, lrate, &mut rng)?;
+    output_filter(
+        format!("Distilling into student with {} hidden units", args.hidden),
+        0,
+    );
+    let history = train_iters(&data, &mut rng, iters, |chunk, iter| {
+        let res = distillation.train_handler(chunk, &mut student, &teacher);
+        if iter % 500 == 0 {
+            output_filter(format!("Iter {} - Syncing weights", iter), 0);
+            sync_weights(&student);
+        }
+        res
+    });
+    let accuracy = get_accuracy(&student);
+    let hyperparameters = [
+        ("learning_rate", lrate),
+        ("batch_size", batch_size as f64),
+        ("iterations", iters as f64),
+        ("hidden", args.hidden as f64),
+        ("teacher_hidden", args.teacher_hidden as f64),
+        ("temperature", args.temperature),
+        ("alpha", args.alpha),
+    ];
+    let card = make_card(&student, &data, &hyperparameters, &[], Some(accuracy));
+    publish_weights(&student, card, Some(&history));
+    output_filter(format!("Final Accuracy: {}", accuracy), 0);
+
+    Ok(Html("Done"))
+}
+
+async fn weights_sparse() -> Json<Value> {
+    let model = model::Model::from_weights(get_weights(), (0.0, 0.0));
+    Json(json!(SparseWeights::from_model(&model)))
+}
+
+async fn vae_get() -> Result<Json<VaeWeights>, StatusCode> {
+    let file = File::open(vae_path()).map_err(|_| StatusCode::NOT_FOUND)?;
+    Ok(Json(
+        serde_json::from_reader(file).map_err(|_| StatusCode::NOT_FOUND)?,
+    ))
+}
+
+// trains a fresh variational autoencoder on the training data and stores it next to the weights
+async fn vae_post(
+    Json(args): Json<VaeInfo>,
+    data: Arc<Mutex<Data>>,
+) -> Result<Json<Value>, StatusCode> {
+    if args.hidden == 0 || args.latent == 0 || args.batch_size == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    data_refresh(data.clone());
+    let mut rng = get_rng();
+    let input_size = match data.lock().unwrap().data.first() {
+        Some(example) => example.image.len(),
+        None => {
+            output_filter("No training data to train a vae on".to_string(), 0);
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    };
+    let mut vae = Vae::random_with(
+        input_size,
+        args.hidden,
+        args.latent,
+        args.learning_rate,
+        &mut rng,
+    );
+    output_filter(
+        format!("Training a vae for {} iterations", args.iterations),
+        0,
+    );
+    let mut loss = None;
+    for iter in 0..args.iterations {
+        let chunk = get_sample_block_with(&data.lock().unwrap(), args.batch_size, &mut rng);
+        let (images, _) = util::split_chunk(&chunk);
+        let res = vae.train_with(images, &mut rng);
+        output_filter(
+            format!(
+                "Iter {} - Reconstruction: {:.4} KL {:.4}",
+                iter, res.reconstruction, res.kl
+            ),
+            1,
+        );
+        loss = Some(res);
+    }
+    let file = File::create(vae_path()).unwrap();
+    serde_json::to_writer(file, &vae.export()).unwrap();
+    Ok(Json(json!({ "loss": loss })))
+}
+
+// trains a classifier of any kind and reports the same metrics on the validation split
+async fn classifiers_post(
+    Json(args): Json<ClassifierInfo>,
+    data: Arc<Mutex<Data>>,
+) -> Result<Json<Value>, StatusCode> {
+    let kind = ClassifierKind::from_name(&args.kind).ok_or(StatusCode::BAD_REQUEST)?;
+    if args.samples == 0 || args.batch_size == 0 || args.hidden == 0 || args.k == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    data_refresh(data.clone());
+    // fitting can take a while, so it works on a copy rather than holding the shared data
+    let train = data.lock().unwrap().clone();
+    if train.data.is_empty() {
+        output_filter("No training data to fit a classifier on".to_string(), 0);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let validation = Data {
+        data: validation_data()
+            .data
+            .iter()
+            .take(args.samples)
+            .cloned()
+            .collect(),
+    };
+    Ok(Json(train_classifier(kind, &args, &train, &validation)))
+}
+
+fn train_classifier(
+    kind: ClassifierKind,
+    args: &ClassifierInfo,
+    train: &Data,
+    test: &Data,
+) -> Value {
+    let classes = get_classes();
+    let input_size = train.data[0].image.len();
+    let mut classifier: Box<dyn Classifier> = match kind {
+        ClassifierKind::Mlp => Box::new(model::Model::random_with(
+            input_size,
+            args.hidden,
+            classes.clone(),
+            (args.learning_rate, args.learning_rate),
+            &mut get_rng(),
+        )),
+        ClassifierKind::Knn => Box::new(Knn::new(args.k, 0.5, classes.len())),
+        ClassifierKind::LogisticRegression => Box::new(LogisticRegression::new(
+            input_size,
+            classes.len(),
+            args.learning_rate,
+            args.l2,
+        )),
+    };
+    let options = FitOptions {
+        iterations: args.iterations,
+        batch_size: args.batch_size,
+        seed: get_env_opt("SEED").map(|x| x.parse::<u64>().unwrap()),
+    };
+    output_filter(format!("Fitting a {:?} classifier", kind), 0);
+    let start = Instant::now();
+    classifier.fit(train, &options);
+    let fit_seconds = start.elapsed().as_secs_f64();
+
+    let (images, targets) = util::split_chunk(&test.data);
+    let start = Instant::now();
+    let metrics = classifier::evaluate(classifier.as_ref(), images, &targets, &classes);
+    let predict_seconds = start.elapsed().as_secs_f64();
+    output_filter(format!("{:?} accuracy {:.4}", kind, metrics.accuracy), 0);
+
+    let file = File::create(classifier_path(kind)).unwrap();
+    serde_json::to_writer(file, &classifier.serialise()).unwrap();
+    json!({
+        "kind": kind,
+        "fit_seconds": fit_seconds,
+        "predict_seconds": predict_seconds,
+        "metrics": metrics,
+    })
+}
+
+fn classifier_path(kind: ClassifierKind) -> String {
+    let name = serde_json::to_value(kind).unwrap();
+    format!("{}.{}.json", get_env("WEIGHTS"), name.as_str().unwrap())
+}
+
+fn vae_path() -> String {
+    format!("{}.vae.json", get_env("WEIGHTS"))
+}
+
+async fn weights_prune(
+    Json(args): Json<PruneInfo>,
+    data: Arc<Mutex<Data>>,
+) -> Result<Json<Value>, StatusCode> {
+    let lrate = get_env("LEARNING_RATE").parse::<f64>().unwrap();
+    let batch_size = get_env("BATCH_SIZE").parse::<usize>().unwrap();
+    let weights = get_weights();
+    let mut model = model::Model::from_weights(weights.clone(), (lrate, lrate));
+    // only ever narrows, and always leaves some weights
+    if !(0.0..1.0).contains(&args.sparsity)
+        || args
+            .hidden
+            .is_some_and(|x| x == 0 || x >= model.weights.0.ncols())
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    if let Some(hidden) = args.hidden {
+        output_filter(format!("Pruning to {} hidden units", hidden), 0);
+        model = prune::structured_prune(&model, hidden);
+    }
+    output_filter(format!("Pruning to {} sparsity", args.sparsity), 0);
+    let mask = prune::magnitude_prune(&mut model, args.sparsity);
+
+    let history = match args.fine_tune_iters {
+        0 => None,
+        iters => {
+            data_refresh(data.clone());
+            let mut rng = get_rng();
+            Some(train_iters(&data, &mut rng, iters, |chunk, _| {
+                mask.train_handler(chunk, &mut model, batch_size)
+            }))
+        }
+    };
+    let accuracy = get_accuracy(&model);
+    let (temperature, ood) = fit_validation(&model);
+    let mut card = ModelCard {
+        temperature: Some(temperature),
+        ood: Some(ood),
+        ..inherited_card(&model, &weights)
+    };
+    card.hyperparameters
+        .insert("sparsity".to_string(), mask.sparsity());
+    card.hyperparameters
+        .insert("fine_tune_iters".to_string(), args.fine_tune_iters as f64);
+    card.accuracy = Some(accuracy);
+    publish_weights(&model, card, history.as_ref());
+    output_filter(format!("Final Accuracy: {}", accuracy), 0);
+
+    Ok(Json(json!({
+        "sparsity": mask.sparsity(),
+        "hidden": model.weights.0.ncols(),
+        "accuracy": accuracy,
+    })))
+}
+
+// a fresh model sized for the loaded data, which has to have at least one example
+fn new_model(
+    data: &Arc<Mutex<Data>>,
+    hidden: usize,
+    lrate: f64,
+    rng: &mut StdRng,
+) -> Result<model::Model, StatusCode> {
+    let Some(input_size) = data.lock().unwrap().data.first().map(|x| x.image.len()) else {
+        output_filter("No training data to train on".to_string(), 0);
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    };
+    Ok(model::Model::random_with(
+        input_size,
+        hidden,
+        get_classes(),
         (lrate, lrate),
-    )
-    .clone();
+        rng,
+    ))
+}
 
+fn train_iters<F>(
+    data: &Arc<Mutex<Data>>,
+    rng: &mut StdRng,
+    iters: usize,
+    mut step: F,
+) -> TrainingHistory
+where
+    F: FnMut(&[DataSingle], usize) -> (f64, f64),
+{
+    let batch_size = get_env("BATCH_SIZE").parse::<usize>().unwrap();
+    let lrate = get_env("LEARNING_RATE").parse::<f64>().unwrap();
+    // batches are sampled at random, so an epoch is just as many of them as cover the data once
+    let epoch_iters = (data.lock().unwrap().data.len() / batch_size).max(1);
     output_filter(format!("Training for {} iterations", iters), 0);
 
-    let mut iter = 0;
-    while iter < iters {
-        let data = data.lock().unwrap();
-        let (loss, accuracy) = train_handler_wrapper(&data, &mut model, batch_size);
+    let start = Instant::now();
+    let mut history = TrainingHistory::new();
+    for iter in 0..iters {
+        let chunk = get_sample_block_with(&data.lock().unwrap(), batch_size, rng);
+        let (loss, accuracy) = step(&chunk, iter);
+        history.record_step(loss, accuracy, lrate, start.elapsed().as_secs_f64());
+        if (iter + 1) % epoch_iters == 0 || iter + 1 == iters {
+            history.end_epoch(start.elapsed().as_secs_f64());
+        }
         output_filter(
             format!(
                 "Iter {} -  Loss: {:.4} Accuracy {:.4}",
@@ -138,16 +1208,155 @@ async fn weights_delete(data: Arc<Mutex<Data>>) -> Html<&'static str> {
             ),
             1,
         );
-        if iter % 500 == 0 {
-            output_filter(format!("Iter {} - Syncing weights", iter), 0);
-            sync_weights(&model);
+    }
+    history
+}
+
+// `cargo run -- search` tries configurations against a validation split instead of serving
+fn hyperparameter_search() {
+    let (train, validation) = split_validation(preprocess_data(load_data("train")));
+    let train = Arc::new(Mutex::new(train));
+    let (images, targets) = util::split_chunk(&validation.data);
+    let space = SearchSpace {
+        learning_rates: get_list("SEARCH_LEARNING_RATES", "0.01,0.035,0.1"),
+        batch_sizes: get_list("SEARCH_BATCH_SIZES", "32,128"),
+        hiddens: get_list("SEARCH_HIDDEN", "64,128,256"),
+        optimizers: get_list::<String>("SEARCH_OPTIMIZERS", "sgd,momentum,adam")
+            .into_iter()
+            .map(|name| {
+                Optimizer::from_name(&name)
+                    .unwrap_or_else(|| panic!("SEARCH_OPTIMIZERS {} is not supported", name))
+            })
+            .collect(),
+        weight_decays: get_list("SEARCH_WEIGHT_DECAYS", "0,0.0001"),
+    };
+    let strategy = match get_env_opt("SEARCH") {
+        Some(name) => {
+            Strategy::from_name(&name).unwrap_or_else(|| panic!("SEARCH {} is not supported", name))
+        }
+        None => Strategy::Grid,
+    };
+    let iters = get_env_opt("SEARCH_ITER")
+        .map(|x| x.parse::<usize>().unwrap())
+        .unwrap_or_else(|| get_env("TRAIN_ITER").parse::<usize>().unwrap());
+    let mut rng = get_rng();
+    let input_size = train.lock().unwrap().data[0].image.len();
+
+    let mut trial_rng = get_rng();
+    let train_config = |config: &Config, state: Option<(model::Model, Trainer)>, steps: usize| {
+        let (mut model, mut trainer) = state.unwrap_or_else(|| {
+            let lrate = (config.learning_rate, config.learning_rate);
+            (
+                model::Model::random_with(
+                    input_size,
+                    config.hidden,
+                    get_classes(),
+                    lrate,
+                    &mut trial_rng,
+                ),
+                Trainer::new(config.optimizer, config.weight_decay),
+            )
+        });
+        for _ in 0..steps {
+            let chunk =
+                get_sample_block_with(&train.lock().unwrap(), config.batch_size, &mut trial_rng);
+            trainer.train_handler(&chunk, &mut model, config.batch_size);
+        }
+        let accuracy = federated::accuracy(&model, images.clone(), &targets);
+        output_filter(
+            format!("{:?} - Validation accuracy {:.4}", config, accuracy),
+            0,
+        );
+        ((model, trainer), accuracy)
+    };
+    let outcome = match strategy {
+        Strategy::Grid => search::evaluate_all(space.grid(), iters, train_config),
+        Strategy::Random => {
+            let trials = get_env_opt("SEARCH_TRIALS").map_or(20, |x| x.parse::<usize>().unwrap());
+            let configs = (0..trials).map(|_| space.sample_with(&mut rng)).collect();
+            search::evaluate_all(configs, iters, train_config)
+        }
+        Strategy::Hyperband => {
+            let eta = get_env_opt("SEARCH_ETA").map_or(3, |x| x.parse::<usize>().unwrap());
+            search::hyperband(&space, iters, eta, &mut rng, train_config)
         }
-        iter += 1;
+    };
+
+    let results = search::results_csv(&outcome.trials);
+    let path = get_env_opt("SEARCH_RESULTS")
+        .unwrap_or_else(|| format!("{}.search.csv", get_env("WEIGHTS")));
+    std::fs::write(&path, &results).unwrap();
+    output_filter(
+        format!("Wrote {} trials to {}", outcome.trials.len(), path),
+        0,
+    );
+    output_filter(results, 1);
+
+    let Some((config, (model, _), accuracy)) = outcome.best else {
+        return;
+    };
+    output_filter(
+        format!("Best {:?} - Validation accuracy {:.4}", config, accuracy),
+        0,
+    );
+    if get_env_opt("SEARCH_PROMOTE").is_some() {
+        let hyperparameters = config.hyperparameters();
+        let temperature = calibration::fit_temperature(&model, images.clone(), &targets);
+        let card = ModelCard {
+            temperature: Some(temperature),
+            ood: Some(fit_ood(&model, images, &targets, temperature, None, None)),
+            ..make_card(
+                &model,
+                &train,
+                &hyperparameters,
+                &[("optimizer", config.optimizer.name())],
+                Some(get_accuracy(&model)),
+            )
+        };
+        let version = publish_weights(&model, card, None);
+        output_filter(
+            format!("Promoted the best weights as version {}", version),
+            0,
+        );
     }
-    sync_weights(&model);
-    output_filter(format!("Final Accuracy: {}", get_accuracy(&model)), 0);
+}
 
-    Html("Done")
+// the last VALIDATION_SIZE training examples, which the search never trains on
+fn split_validation(mut data: Data) -> (Data, Data) {
+    let size = get_env_opt("VALIDATION_SIZE").map_or(10000, |x| x.parse::<usize>().unwrap());
+    let len = data.data.len();
+    if size == 0 || (len > 0 && size >= len) {
+        panic!(
+            "VALIDATION_SIZE {} must be at least 1 and leave training data out of {} examples",
+            size, len
+        );
+    }
+    let validation = data.data.split_off(len.saturating_sub(size));
+    (data, Data { data: validation })
+}
+
+fn validation_data() -> &'static Data {
+    VALIDATION_DATA.get_or_init(|| split_validation(preprocess_data(load_data("train"))).1)
+}
+
+fn get_list<T: std::str::FromStr>(name: &str, default: &str) -> Vec<T>
+where
+    T::Err: std::fmt::Debug,
+{
+    let list = get_env_opt(name).unwrap_or_else(|| default.to_string());
+    let values = list
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            x.parse::<T>()
+                .unwrap_or_else(|err| panic!("{} has an invalid entry {:?}: {:?}", name, x, err))
+        })
+        .collect::<Vec<T>>();
+    if values.is_empty() {
+        panic!("{} must list at least one value", name);
+    }
+    values
 }
 
 fn check_envs() {
@@ -176,7 +1385,7 @@ fn check_envs() {
     if std::env::var("OUTPUT_LEVEL").is_err() {
         res.push("OUTPUT_LEVEL");
     }
-    if res.len() != 0 {
+    if !res.is_empty() {
         println!("ENV VARS: {} are not set", res.join(", "));
         panic!("ENV VARS: {} are not set", res.join(", "));
     }
@@ -186,6 +1395,33 @@ fn get_env(name: &str) -> String {
     std::env::var(name).unwrap()
 }
 
+fn get_env_opt(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|x| !x.is_empty())
+}
+
+fn get_dataset() -> Dataset {
+    match get_env_opt("DATASET") {
+        Some(name) => {
+            Dataset::from_name(&name).unwrap_or_else(|| panic!("DATASET {} is not supported", name))
+        }
+        None => Dataset::Mnist,
+    }
+}
+
+fn get_classes() -> Vec<String> {
+    match get_env_opt("CLASSES") {
+        Some(classes) => classes.split(',').map(|x| x.trim().to_string()).collect(),
+        None => get_dataset().classes(),
+    }
+}
+
+fn get_rng() -> StdRng {
+    match get_env_opt("SEED") {
+        Some(seed) => StdRng::seed_from_u64(seed.parse::<u64>().unwrap()),
+        None => StdRng::from_entropy(),
+    }
+}
+
 fn output_filter(input: String, level: usize) {
     if get_env("OUTPUT_LEVEL").parse::<usize>().unwrap() > level {
         println!("{}", input);
@@ -208,22 +1444,253 @@ fn get_weights() -> Weights {
 }
 
 fn sync_weights(model: &model::Model) {
+    write_weights(&model.export());
+}
+
+fn publish_weights(
+    model: &model::Model,
+    card: ModelCard,
+    history: Option<&TrainingHistory>,
+) -> u64 {
     let weights = Weights {
-        weights: model.export_weights(),
+        card: Some(card),
+        ..model.export()
     };
     write_weights(&weights);
+    let version = save_version(&weights);
+    if let Some(history) = history {
+        save_history(version, history);
+    }
+    version
+}
+
+fn versions_dir() -> String {
+    get_env("WEIGHTS") + ".versions"
+}
+
+fn list_versions() -> Vec<u64> {
+    let mut res = match std::fs::read_dir(versions_dir()) {
+        Ok(dir) => dir
+            .filter_map(|x| {
+                x.ok()?
+                    .file_name()
+                    .to_str()?
+                    .strip_suffix(".json")?
+                    .parse::<u64>()
+                    .ok()
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    res.sort();
+    res
+}
+
+fn save_version(weights: &Weights) -> u64 {
+    static TEMP: AtomicU64 = AtomicU64::new(0);
+    std::fs::create_dir_all(versions_dir()).unwrap();
+    let temp = format!(
+        "{}/{}-{}.tmp",
+        versions_dir(),
+        std::process::id(),
+        TEMP.fetch_add(1, Ordering::Relaxed)
+    );
+    serde_json::to_writer(File::create(&temp).unwrap(), weights).unwrap();
+    // linking fails if the number is taken, so concurrent publishers each claim their own
+    let mut version = list_versions().last().map_or(1, |x| x + 1);
+    loop {
+        match std::fs::hard_link(&temp, format!("{}/{}.json", versions_dir(), version)) {
+            Ok(()) => break,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => version += 1,
+            Err(err) => panic!("Could not save weights version {}: {}", version, err),
+        }
+    }
+    std::fs::remove_file(temp).unwrap();
+    output_filter(format!("Saved weights version {}", version), 1);
+    remove_old_versions();
+    version
+}
+
+// keeps the newest VERSIONS_KEEP versions
+fn remove_old_versions() {
+    let keep = get_env_opt("VERSIONS_KEEP").map_or(100, |x| x.parse::<usize>().unwrap());
+    let versions = list_versions();
+    for version in &versions[..versions.len().saturating_sub(keep.max(1))] {
+        let _ = std::fs::remove_file(format!("{}/{}.json", versions_dir(), version));
+        let _ = std::fs::remove_file(format!("{}/{}.history.json", versions_dir(), version));
+        output_filter(format!("Removed weights version {}", version), 1);
+    }
+}
+
+// describes a model freshly trained on the loaded data
+fn make_card(
+    model: &model::Model,
+    data: &Arc<Mutex<Data>>,
+    hyperparameters: &[(&str, f64)],
+    settings: &[(&str, &str)],
+    accuracy: Option<f64>,
+) -> ModelCard {
+    let mut preprocessing = vec!["grayscale scaled to [0, 1]".to_string()];
+    if get_dataset().transposed() {
+        preprocessing.push("transposed".to_string());
+    }
+    if let Some(threshold) = get_env_opt("BINARIZE") {
+        preprocessing.push(format!("binarized above {}", threshold));
+    }
+    ModelCard {
+        preprocessing,
+        dataset: Some(get_env_opt("DATASET").unwrap_or_else(|| "mnist".to_string())),
+        dataset_hash: Some(util::dataset_hash(&data.lock().unwrap())),
+        hyperparameters: hyperparameters
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect(),
+        settings: settings
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        accuracy,
+        ..ModelCard::from_model(model)
+    }
+}
+
+// the card of the weights a model was derived from, with its shape brought up to date and
+// without what was fitted to the old weights
+fn inherited_card(model: &model::Model, weights: &Weights) -> ModelCard {
+    let fresh = ModelCard::from_model(model);
+    match weights.card.clone() {
+        Some(card) => ModelCard {
+            layers: fresh.layers,
+            accuracy: None,
+            temperature: None,
+            ood: None,
+            ..card
+        },
+        None => fresh,
+    }
+}
+
+fn save_history(version: u64, history: &TrainingHistory) {
+    let file = File::create(format!("{}/{}.history.json", versions_dir(), version)).unwrap();
+    serde_json::to_writer(file, history).unwrap();
+}
+
+fn get_history(version: u64) -> Option<TrainingHistory> {
+    let file = File::open(format!("{}/{}.history.json", versions_dir(), version)).ok()?;
+    serde_json::from_reader(file).ok()
+}
+
+fn get_version(version: u64) -> Option<Weights> {
+    let file = File::open(format!("{}/{}.json", versions_dir(), version)).ok()?;
+    serde_json::from_reader(file).ok()
 }
 
 fn data_refresh(data: Arc<Mutex<Data>>) {
     let mut data = data.lock().unwrap();
-    if data.data.len() != 0 {
+    if !data.data.is_empty() {
         return;
     }
-    output_filter(format!("Loading training data"), 1);
-    *data = read_data(
-        format!("{}/xtrain.csv", get_env("DATA")),
-        format!("{}/ytrain.csv", get_env("DATA")),
+    output_filter("Loading training data".to_string(), 1);
+    // the validation split is left for calibration and the search
+    let (train, validation) = split_validation(preprocess_data(load_data("train")));
+    let _ = VALIDATION_DATA.set(validation);
+    *data = train;
+}
+
+// the softmax temperature and the out of distribution detector, both fitted on the validation split
+fn fit_validation(model: &model::Model) -> (f64, OodDetector) {
+    let (images, targets) = util::split_chunk(&validation_data().data);
+    let temperature = calibration::fit_temperature(model, images.clone(), &targets);
+    output_filter(format!("Fitted temperature {:.4}", temperature), 0);
+    let ood = fit_ood(model, images, &targets, temperature, None, None);
+    (temperature, ood)
+}
+
+fn fit_ood(
+    model: &model::Model,
+    images: Vec<Vec<f64>>,
+    targets: &[u8],
+    temperature: f64,
+    method: Option<OodMethod>,
+    accept: Option<f64>,
+) -> OodDetector {
+    let method = method.unwrap_or_else(|| match get_env_opt("OOD_METHOD") {
+        Some(name) => OodMethod::from_name(&name)
+            .unwrap_or_else(|| panic!("OOD_METHOD {} is not supported", name)),
+        None => OodMethod::Energy,
+    });
+    let accept = accept
+        .or_else(|| get_env_opt("OOD_ACCEPT").map(|x| x.parse::<f64>().unwrap()))
+        .unwrap_or(0.95);
+    let ood = OodDetector::fit(model, images, targets, method, temperature, accept);
+    output_filter(
+        format!("Fitted {:?} rejection above {:.4}", method, ood.threshold),
+        0,
     );
+    ood
+}
+
+// the served detector, with OOD_THRESHOLD overriding the fitted threshold
+fn get_detector(weights: &Weights) -> Option<OodDetector> {
+    let mut ood = weights.card.as_ref()?.ood.clone()?;
+    let hidden = weights.weights.1.len();
+    let classes = weights.weights.1.first().map_or(0, |x| x.len());
+    if !ood.fits(hidden, classes) {
+        output_filter(
+            "Ignoring an out of distribution detector fitted to another model shape".to_string(),
+            1,
+        );
+        return None;
+    }
+    if let Some(threshold) = get_env_opt("OOD_THRESHOLD") {
+        ood.threshold = threshold.parse::<f64>().unwrap();
+    }
+    Some(ood)
+}
+
+// the preprocessed test split, read on first use and kept for the life of the process
+fn test_data() -> &'static Data {
+    static TEST_DATA: OnceLock<Data> = OnceLock::new();
+    TEST_DATA.get_or_init(|| preprocess_data(load_data("test")))
+}
+
+fn load_data(split: &str) -> Data {
+    let dir = get_env("DATA");
+    let prefix = match split {
+        "train" => "train",
+        _ => "t10k",
+    };
+    let images = find_idx(&dir, &format!("{}-images-idx3-ubyte", prefix));
+    let labels = find_idx(&dir, &format!("{}-labels-idx1-ubyte", prefix));
+    let data = match (images, labels) {
+        (Some(images), Some(labels)) => {
+            output_filter(format!("Reading idx data from {}", images), 1);
+            model::idx::read_data(images, labels).unwrap()
+        }
+        _ => read_data(
+            format!("{}/x{}.csv", dir, split),
+            format!("{}/y{}.csv", dir, split),
+        ),
+    };
+    get_dataset()
+        .prepare(data, get_classes().len())
+        .unwrap_or_else(|err| panic!("Invalid {} data: {}", split, err))
+}
+
+fn find_idx(dir: &str, name: &str) -> Option<String> {
+    [format!("{}/{}", dir, name), format!("{}/{}.gz", dir, name)]
+        .into_iter()
+        .find(|path| std::path::Path::new(path).is_file())
+}
+
+fn preprocess_data(mut data: Data) -> Data {
+    if let Some(threshold) = get_env_opt("BINARIZE") {
+        let threshold = threshold.parse::<f64>().unwrap();
+        for single in data.data.iter_mut() {
+            single.image = preprocess::binarize(&single.image, threshold);
+        }
+    }
+    data
 }
 
 fn read_data(xdata: String, ydata: String) -> Data {
@@ -234,43 +1701,37 @@ fn read_data(xdata: String, ydata: String) -> Data {
     for (x, y) in xreader.records().zip(yreader.records()) {
         let x = x.unwrap();
         let y = y.unwrap();
-        let mut xdata_single = Vec::new();
-        for i in 0..x.len() {
-            if x[i].parse::<f64>().unwrap() > 0.0 {
-                xdata_single.push(1.0);
-            } else {
-                xdata_single.push(0.0);
-            }
-        }
-        xdata.push(xdata_single);
+        let xdata_single = x
+            .iter()
+            .map(|i| i.parse::<f64>().unwrap())
+            .collect::<Vec<f64>>();
+        xdata.push(preprocess::normalize(&xdata_single, 255.0));
         ydata.push(y[0].parse::<f64>().unwrap().round() as u8);
     }
     Data {
         data: xdata
             .into_iter()
-            .zip(ydata.into_iter())
+            .zip(ydata)
             .map(|(image, target)| DataSingle { image, target })
             .collect(),
     }
 }
 
 fn get_accuracy(model: &model::Model) -> f64 {
-    let xdata = get_env("DATA") + "/xtest.csv";
-    let ydata = get_env("DATA") + "/ytest.csv";
     let mut iters = get_env("TEST_ITER").parse::<usize>().unwrap();
     let batch_size = get_env("BATCH_SIZE").parse::<usize>().unwrap();
-    let data = read_data(xdata, ydata);
+    let data = test_data();
 
     output_filter(format!("Testing for {} iterations", iters), 1);
     let mut accuracies = Vec::new();
     while iters > 0 {
-        let chunk = get_sample_block(&data, batch_size);
+        let chunk = get_sample_block(data, batch_size);
         let (images, targets): (Vec<Vec<f64>>, Vec<u8>) =
             chunk
                 .into_iter()
                 .fold((Vec::new(), Vec::new()), |(mut images, mut targets), x| {
                     images.push(x.image.clone());
-                    targets.push(x.target as u8);
+                    targets.push(x.target);
                     (images, targets)
                 });
         accuracies.push(
@@ -291,16 +1752,21 @@ fn get_accuracy(model: &model::Model) -> f64 {
 mod tests {
     use super::*;
 
-    async fn setup() {
+    static WEIGHTS_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    // fresh random weights, held by the test until the guard drops
+    async fn setup() -> tokio::sync::MutexGuard<'static, ()> {
+        let guard = WEIGHTS_LOCK.lock().await;
         dotenv().ok();
-        if File::open(get_env("WEIGHTS")).is_ok() {
-            std::fs::remove_file(get_env("WEIGHTS")).unwrap();
-        }
-        let _ = File::create(get_env("WEIGHTS")).unwrap();
-        let _ = weights_post(Json(Weights {
-            weights: (util::random_dist(784, 128), util::random_dist(128, 10)),
-        }))
-        .await;
+        let model = model::Model::random(
+            util::IMAGE_SIZE,
+            util::HIDDEN_SIZE,
+            Dataset::Mnist.classes(),
+            (0.1, 0.1),
+        );
+        write_weights(&model.export());
+        save_version(&model.export());
+        guard
     }
 
     #[tokio::test]
@@ -311,18 +1777,573 @@ mod tests {
 
     #[tokio::test]
     async fn test_weights_patch() {
-        setup().await;
-        let response = weights_patch(Json(Data {
-            data: vec![DataSingle {
-                target: 1,
-                image: vec![0.0; 784],
-            }],
-        }))
+        let _weights = setup().await;
+        let response = weights_patch(
+            Query(ReplayQuery {
+                replay: Some(0),
+                ewc: None,
+            }),
+            Json(Data {
+                data: vec![DataSingle {
+                    target: 1,
+                    image: vec![0.0; util::IMAGE_SIZE],
+                }],
+            }),
+            Arc::new(Mutex::new(Replay::default())),
+            Arc::new(Mutex::new(Data { data: Vec::new() })),
+        )
         .await;
         assert!(util::approximate_equal(
             response.0["loss"].as_f64().unwrap(),
-            2.30258509,
+            std::f64::consts::LN_10,
             None
         ));
     }
+
+    #[tokio::test]
+    async fn test_weights_patch_replay() {
+        let _weights = setup().await;
+        let example = |target| DataSingle {
+            target,
+            image: vec![target as f64 / 10.0; util::IMAGE_SIZE],
+        };
+        let train = Data {
+            data: (0..6).map(example).collect(),
+        };
+        let response = weights_patch(
+            Query(ReplayQuery {
+                replay: Some(4),
+                ewc: Some(1.0),
+            }),
+            Json(Data {
+                data: vec![example(7)],
+            }),
+            Arc::new(Mutex::new(Replay::default())),
+            Arc::new(Mutex::new(train)),
+        )
+        .await;
+        assert!(response.0["loss"].as_f64().unwrap().is_finite());
+        assert_eq!(response.0["replay"], 4);
+    }
+
+    #[tokio::test]
+    async fn test_predict() {
+        let _weights = setup().await;
+        let images = vec![vec![0.0; util::IMAGE_SIZE], vec![1.0; util::IMAGE_SIZE]];
+        let response = predict_post(Json(ImagesInfo { images })).await.unwrap();
+        let predictions = response.0.as_array().unwrap();
+        assert_eq!(predictions.len(), 2);
+        for prediction in predictions {
+            let probabilities = prediction["probabilities"].as_array().unwrap();
+            let sum = probabilities
+                .iter()
+                .map(|x| x.as_f64().unwrap())
+                .sum::<f64>();
+            assert!(util::approximate_equal(sum, 1.0, None));
+            let index = prediction["prediction"].as_u64().unwrap() as usize;
+            assert_eq!(prediction["confidence"], probabilities[index]);
+            // the test weights have no detector, so nothing is rejected
+            assert_eq!(prediction["recognised"], true);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_number() {
+        let _weights = setup().await;
+        // two vertical strokes on a 28x84 canvas
+        let width = 84;
+        let mut image = vec![0.0; 28 * width];
+        for row in 4..24 {
+            image[row * width + 10] = 1.0;
+            image[row * width + 60] = 1.0;
+        }
+        let response = number_post(Json(NumberInfo {
+            image: image.clone(),
+            width,
+            segmentation: segment::Segmentation::ColumnProjection,
+        }))
+        .await
+        .unwrap();
+        assert_eq!(response.0["digits"].as_array().unwrap().len(), 2);
+        assert_eq!(response.0["text"].as_str().unwrap().len(), 2);
+
+        let response = expression_post(Json(NumberInfo {
+            image: image.clone(),
+            width,
+            segmentation: Default::default(),
+        }))
+        .await
+        .unwrap();
+        assert_eq!(response.0["reading"]["digits"].as_array().unwrap().len(), 2);
+
+        let response = number_post(Json(NumberInfo {
+            image,
+            width: 83,
+            segmentation: Default::default(),
+        }))
+        .await;
+        assert_eq!(response.unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_vae() {
+        dotenv().ok();
+        let data = Data {
+            data: (0..4)
+                .map(|i| DataSingle {
+                    target: i,
+                    image: vec![i as f64 / 4.0; util::IMAGE_SIZE],
+                })
+                .collect(),
+        };
+        let args = VaeInfo {
+            hidden: 8,
+            latent: 2,
+            iterations: 3,
+            batch_size: 2,
+            learning_rate: 0.001,
+        };
+        let response = vae_post(Json(args), Arc::new(Mutex::new(data)))
+            .await
+            .unwrap();
+        assert!(response.0["loss"]["kl"].as_f64().unwrap().is_finite());
+        let vae = Vae::from_weights(vae_get().await.unwrap().0, 0.0);
+        assert_eq!(vae.input_size(), util::IMAGE_SIZE);
+        assert_eq!(vae.latent_size(), 2);
+    }
+
+    #[test]
+    fn test_classifiers() {
+        dotenv().ok();
+        let data = Data {
+            data: (0..20)
+                .map(|i| DataSingle {
+                    target: i % 10,
+                    image: vec![(i % 10) as f64 / 10.0; util::IMAGE_SIZE],
+                })
+                .collect(),
+        };
+        for kind in [
+            ClassifierKind::Mlp,
+            ClassifierKind::Knn,
+            ClassifierKind::LogisticRegression,
+        ] {
+            let args = ClassifierInfo {
+                kind: String::new(),
+                k: 1,
+                hidden: 8,
+                iterations: 2,
+                batch_size: 4,
+                learning_rate: 0.01,
+                l2: 0.0,
+                samples: 10,
+            };
+            let response = train_classifier(kind, &args, &data, &data);
+            let metrics = &response["metrics"];
+            assert_eq!(metrics["confusion"].as_array().unwrap().len(), 10);
+            assert!(metrics["accuracy"].as_f64().unwrap() <= 1.0);
+            let file = File::open(classifier_path(kind)).unwrap();
+            let saved: classifier::SavedClassifier = serde_json::from_reader(file).unwrap();
+            assert_eq!(saved.load().kind(), kind);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_classifiers_unsupported() {
+        let args = serde_json::from_value(json!({ "kind": "forest" })).unwrap();
+        let response =
+            classifiers_post(Json(args), Arc::new(Mutex::new(Data { data: vec![] }))).await;
+        assert_eq!(response.unwrap_err(), StatusCode::BAD_REQUEST);
+        for args in [
+            json!({ "kind": "knn", "samples": 0 }),
+            json!({ "kind": "mlp", "batch_size": 0 }),
+            json!({ "kind": "mlp", "hidden": 0 }),
+            json!({ "kind": "knn", "k": 0 }),
+        ] {
+            let args = serde_json::from_value(args).unwrap();
+            let response =
+                classifiers_post(Json(args), Arc::new(Mutex::new(Data { data: vec![] }))).await;
+            assert_eq!(response.unwrap_err(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn test_save_version_concurrent() {
+        dotenv().ok();
+        let weights = model::Model::random(
+            util::IMAGE_SIZE,
+            util::HIDDEN_SIZE,
+            Dataset::Mnist.classes(),
+            (0.1, 0.1),
+        )
+        .export();
+        let handles = (0..8)
+            .map(|_| {
+                let weights = weights.clone();
+                std::thread::spawn(move || save_version(&weights))
+            })
+            .collect::<Vec<_>>();
+        let mut versions = handles
+            .into_iter()
+            .map(|x| x.join().unwrap())
+            .collect::<Vec<u64>>();
+        versions.sort();
+        versions.dedup();
+        assert_eq!(versions.len(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_weights_prune_invalid() {
+        let _weights = setup().await;
+        let prune = |args: Value| {
+            weights_prune(
+                Json(serde_json::from_value(args).unwrap()),
+                Arc::new(Mutex::new(Data { data: Vec::new() })),
+            )
+        };
+        for args in [
+            json!({ "hidden": 0 }),
+            json!({ "hidden": util::HIDDEN_SIZE }),
+            json!({ "sparsity": 1.0 }),
+            json!({ "sparsity": -0.1 }),
+        ] {
+            assert_eq!(prune(args).await.unwrap_err(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_weights_distill_invalid() {
+        dotenv().ok();
+        let distill = |args: Value| {
+            weights_distill(
+                Json(serde_json::from_value(args).unwrap()),
+                Arc::new(Mutex::new(Data { data: Vec::new() })),
+            )
+        };
+        for args in [
+            json!({ "temperature": 0.0 }),
+            json!({ "temperature": -1.0 }),
+            json!({ "hidden": 0 }),
+            json!({ "teacher_hidden": 0 }),
+            json!({ "alpha": 1.5 }),
+            json!({ "alpha": -0.1 }),
+        ] {
+            assert_eq!(distill(args).await.unwrap_err(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn test_get_list() {
+        std::env::set_var("TEST_LIST", " 1, 2 ,,3");
+        assert_eq!(get_list::<usize>("TEST_LIST", "4"), vec![1, 2, 3]);
+        std::env::remove_var("TEST_LIST");
+        assert_eq!(get_list::<usize>("TEST_LIST", "4"), vec![4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "TEST_BAD_LIST has an invalid entry")]
+    fn test_get_list_invalid() {
+        std::env::set_var("TEST_BAD_LIST", "1,x");
+        get_list::<usize>("TEST_BAD_LIST", "4");
+    }
+
+    #[test]
+    #[should_panic(expected = "TEST_EMPTY_LIST must list at least one value")]
+    fn test_get_list_empty() {
+        get_list::<usize>("TEST_EMPTY_LIST", " , ");
+    }
+
+    #[tokio::test]
+    async fn test_vae_invalid() {
+        dotenv().ok();
+        let empty = Arc::new(Mutex::new(Data { data: Vec::new() }));
+        for args in [
+            json!({ "hidden": 0 }),
+            json!({ "latent": 0 }),
+            json!({ "batch_size": 0 }),
+        ] {
+            let args = serde_json::from_value(args).unwrap();
+            assert_eq!(
+                vae_post(Json(args), empty.clone()).await.unwrap_err(),
+                StatusCode::BAD_REQUEST
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_calibrate_no_bins() {
+        let query = CalibrationQuery { bins: Some(0) };
+        assert_eq!(
+            calibrate_post(Query(query)).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ood_invalid_accept() {
+        for accept in [0.0, 1.5, f64::NAN] {
+            let query = OodQuery {
+                method: None,
+                accept: Some(accept),
+            };
+            assert_eq!(
+                ood_post(Query(query)).await.unwrap_err(),
+                StatusCode::BAD_REQUEST
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_robustness_invalid() {
+        dotenv().ok();
+        let args = serde_json::from_value(json!({ "samples": 0 })).unwrap();
+        assert_eq!(
+            robustness_post(Json(args)).await.unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn test_read_data() {
+        let dir = std::env::temp_dir();
+        let xdata = dir.join("mnist_wasm_test_x.csv");
+        let ydata = dir.join("mnist_wasm_test_y.csv");
+        std::fs::write(&xdata, "a,b,c\n0,51,255\n255,0,0\n").unwrap();
+        std::fs::write(&ydata, "y\n3\n7\n").unwrap();
+        let data = read_data(
+            xdata.to_str().unwrap().to_string(),
+            ydata.to_str().unwrap().to_string(),
+        );
+        assert_eq!(data.data.len(), 2);
+        assert_eq!(data.data[0].image, vec![0.0, 0.2, 1.0]);
+        assert_eq!(data.data[0].target, 3);
+        assert_eq!(data.data[1].target, 7);
+    }
+
+    #[tokio::test]
+    async fn test_federated() {
+        let _weights = setup().await;
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let base = federated_base(&pending.lock().unwrap()).unwrap();
+        let model = model::Model::from_weights(get_version(base).unwrap(), (0.1, 0.1));
+        let mut local = model.clone();
+        local.weights.1 += 1.0;
+        let update = federated::make_update(base, &model, &local, 4);
+
+        let stale = WeightUpdate {
+            base_version: base + 1,
+            ..update.clone()
+        };
+        let response = federated_post(Json(stale), pending.clone()).await;
+        assert_eq!(response.unwrap_err(), StatusCode::CONFLICT);
+
+        let mut wrong = update.clone();
+        wrong.delta.1.pop();
+        let response = federated_post(Json(wrong), pending.clone()).await;
+        assert_eq!(response.unwrap_err(), StatusCode::BAD_REQUEST);
+
+        let greedy = WeightUpdate {
+            samples: usize::MAX,
+            ..update.clone()
+        };
+        let response = federated_post(Json(greedy), pending.clone()).await;
+        assert_eq!(response.unwrap_err(), StatusCode::BAD_REQUEST);
+
+        let response = federated_post(Json(update), pending.clone()).await.unwrap();
+        assert_eq!(response.0["pending"], 1);
+
+        // publishing meanwhile keeps the round on its base and the update is rebased
+        let mut moved = model.clone();
+        moved.weights.0 += 0.5;
+        publish_weights(&moved, ModelCard::from_model(&moved), None);
+        assert_eq!(federated_get(pending.clone()).await.0["version"], base);
+        let round = close_round(&mut pending.lock().unwrap()).unwrap();
+        let version = finish_round(&pending, round).await.unwrap();
+        assert!(version > base);
+        let res = model::Model::from_weights(get_version(version).unwrap(), (0.1, 0.1));
+        let close = |x: f64, y: f64| util::approximate_equal(x, y, None);
+        assert!(res
+            .weights
+            .0
+            .iter()
+            .zip(moved.weights.0.iter())
+            .all(|(x, y)| close(*x, *y)));
+        assert!(res
+            .weights
+            .1
+            .iter()
+            .zip(local.weights.1.iter())
+            .all(|(x, y)| close(*x, *y)));
+        assert!(close_round(&mut pending.lock().unwrap()).is_none());
+        assert_eq!(federated_get(pending).await.0["version"], version);
+    }
+
+    #[tokio::test]
+    async fn test_weights_post_needs_token() {
+        let _weights = setup().await;
+        let weights = get_weights();
+        let response = weights_post(HeaderMap::new(), Json(weights.clone())).await;
+        assert_eq!(response, StatusCode::UNAUTHORIZED);
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer wrong".parse().unwrap());
+        let response = weights_post(headers, Json(weights.clone())).await;
+        assert_eq!(response, StatusCode::UNAUTHORIZED);
+
+        std::env::set_var("ADMIN_TOKEN", "secret");
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let mut card =
+            ModelCard::from_model(&model::Model::from_weights(weights.clone(), (0.0, 0.0)));
+        card.layers[1] += 1;
+        let mismatched = Weights {
+            card: Some(card),
+            ..weights.clone()
+        };
+        let response = weights_post(headers, Json(mismatched)).await;
+        assert_eq!(response, StatusCode::BAD_REQUEST);
+        assert_eq!(get_weights(), weights);
+    }
+
+    #[tokio::test]
+    async fn test_federated_rejects_outliers() {
+        let _weights = setup().await;
+        let base = federated_base(&Pending::default()).unwrap();
+        let model = model::Model::from_weights(get_version(base).unwrap(), (0.1, 0.1));
+        let updates = [0.01, 0.011, 0.009, 10.0]
+            .iter()
+            .map(|x| {
+                let mut local = model.clone();
+                local.weights.1 += *x;
+                federated::make_update(base, &model, &local, 8)
+            })
+            .collect::<Vec<WeightUpdate>>();
+        let (accepted, rejected) = screen_updates(&model, updates, None);
+        assert_eq!(accepted.len(), 3);
+        assert_eq!(rejected.len(), 1);
+        assert!(rejected[0].reason.contains("consensus"));
+        assert_eq!(rejected[0].accuracy_drop, None);
+
+        // held-out images labelled as the base predicts them, so any change can only hurt
+        let images = (0..20)
+            .map(|i| vec![i as f64 / 20.0; util::IMAGE_SIZE])
+            .collect::<Vec<Vec<f64>>>();
+        let targets = model.infer2d(images.clone());
+        let holdout = (images, targets);
+        let mut flipped = model.clone();
+        flipped.weights.1 *= -1.0;
+        let update = federated::make_update(base, &model, &flipped, 8);
+        let (accepted, rejected) = screen_updates(&model, vec![update], Some(&holdout));
+        assert!(accepted.is_empty());
+        assert!(rejected[0].reason.contains("held-out"));
+        assert!(rejected[0].accuracy_drop.unwrap() > 0.05);
+    }
+
+    #[tokio::test]
+    async fn test_activations() {
+        let _weights = setup().await;
+        let images = vec![vec![0.5; util::IMAGE_SIZE]; 3];
+        let response = activations_post(Json(ImagesInfo {
+            images: images.clone(),
+        }))
+        .await
+        .unwrap();
+        let activations = response.0.as_array().unwrap();
+        assert_eq!(activations.len(), 3);
+        assert_eq!(
+            activations[0]["hidden"].as_array().unwrap().len(),
+            util::HIDDEN_SIZE
+        );
+        assert_eq!(activations[0]["logits"].as_array().unwrap().len(), 10);
+        let response = embed_post(Json(ImagesInfo { images })).await.unwrap();
+        assert_eq!(response.0[2].as_array().unwrap().len(), util::HIDDEN_SIZE);
+
+        let wrong = embed_post(Json(ImagesInfo {
+            images: vec![vec![0.5; 3]],
+        }))
+        .await;
+        assert_eq!(wrong.unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_metadata() {
+        let _weights = setup().await;
+        let response = metadata_get().await;
+        assert_eq!(response.0["card"]["input_shape"], json!([28, 28]));
+        assert_eq!(response.0["classes"].as_array().unwrap().len(), 10);
+
+        let model = model::Model::from_weights(get_weights(), (0.1, 0.1));
+        let weights = Weights {
+            card: Some(ModelCard {
+                temperature: Some(2.0),
+                ..ModelCard::from_model(&model)
+            }),
+            ..model.export()
+        };
+        let mut card = inherited_card(&model, &weights);
+        assert_eq!(card.temperature, None);
+        card.hyperparameters.insert("iterations".to_string(), 3.0);
+        let version = publish_weights(&model, card.clone(), None);
+        assert_eq!(get_version(version).unwrap().card, Some(card));
+    }
+
+    #[tokio::test]
+    async fn test_history() {
+        let _weights = setup().await;
+        let model = model::Model::from_weights(get_weights(), (0.1, 0.1));
+        let mut history = TrainingHistory::new();
+        history.record_step(2.0, 0.5, 0.1, 0.25);
+        history.end_epoch(0.25);
+        let card = ModelCard::from_model(&model);
+        let version = publish_weights(&model, card, Some(&history));
+        assert_eq!(get_history(version), Some(history.clone()));
+
+        let query = |format: Option<&str>| HistoryQuery {
+            format: format.map(|x| x.to_string()),
+        };
+        assert!(history_get(Path(version), Query(query(None))).await.is_ok());
+        assert!(history_get(Path(version), Query(query(Some("csv"))))
+            .await
+            .is_ok());
+        let wrong = history_get(Path(version), Query(query(Some("xml")))).await;
+        assert_eq!(wrong.unwrap_err(), StatusCode::BAD_REQUEST);
+        let missing = history_get(Path(u64::MAX), Query(query(None))).await;
+        assert_eq!(missing.unwrap_err(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_ensemble_infer() {
+        let _weights = setup().await;
+        let version = *list_versions().last().unwrap();
+        assert!(get_version(version).is_some());
+        let response = ensemble_infer(Json(EnsembleInfo {
+            versions: vec![version, version],
+            member_weights: vec![0.5, 0.5],
+            combine: Default::default(),
+            images: vec![vec![0.0; util::IMAGE_SIZE]; 3],
+        }))
+        .await
+        .unwrap();
+        assert_eq!(response.0["predictions"].as_array().unwrap().len(), 3);
+        assert_eq!(response.0["labels"].as_array().unwrap().len(), 3);
+
+        let missing = ensemble_infer(Json(EnsembleInfo {
+            versions: vec![u64::MAX],
+            member_weights: Vec::new(),
+            combine: Default::default(),
+            images: vec![vec![0.0; util::IMAGE_SIZE]],
+        }))
+        .await;
+        assert_eq!(missing.unwrap_err(), StatusCode::NOT_FOUND);
+
+        for member_weights in [vec![1.0, -0.5], vec![0.0, 0.0], vec![f64::NAN, 1.0]] {
+            let invalid = ensemble_infer(Json(EnsembleInfo {
+                versions: vec![version, version],
+                member_weights,
+                combine: Default::default(),
+                images: vec![vec![0.0; util::IMAGE_SIZE]],
+            }))
+            .await;
+            assert_eq!(invalid.unwrap_err(), StatusCode::BAD_REQUEST);
+        }
+    }
 }
@@ -77,6 +77,7 @@ impl ActivationFunctions {
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_fold)]
 mod tests {
     use super::*;
 
@@ -0,0 +1,232 @@
+use crate::activations::ActivationFunctions;
+use crate::model::Model;
+use crate::util::{one_hot, split_chunk, to_array2, DataSingle};
+use ndarray::Array2;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Attack {
+    #[default]
+    Fgsm,
+    Pgd,
+}
+
+impl Attack {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace(['_', '-'], "").as_str() {
+            "fgsm" => Some(Self::Fgsm),
+            "pgd" => Some(Self::Pgd),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fgsm => "fgsm",
+            Self::Pgd => "pgd",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RobustnessPoint {
+    pub epsilon: f64,
+    pub accuracy: f64,
+}
+
+// gradient of the cross entropy loss with respect to the inputs
+fn loss_gradients(model: &Model, images: &Array2<f64>, targets: &[u8]) -> Array2<f64> {
+    let (layer1_relu, logits) = model.forward_layers(images.view());
+    let probabilities = ActivationFunctions::logsoftmax2d(logits).mapv(f64::exp);
+    let logit_gradients = probabilities - one_hot(targets, model.num_classes());
+    model.input_gradients(layer1_relu, logit_gradients)
+}
+
+// one signed gradient step of size epsilon, kept to valid pixel values
+pub fn fgsm(model: &Model, images: Vec<Vec<f64>>, targets: &[u8], epsilon: f64) -> Vec<Vec<f64>> {
+    let images = to_array2(images);
+    let gradients = loss_gradients(model, &images, targets);
+    let res = (images + gradients.mapv(f64::signum) * epsilon).mapv(|x| x.clamp(0.0, 1.0));
+    res.rows().into_iter().map(|x| x.to_vec()).collect()
+}
+
+// repeated signed gradient steps, projected back into the L∞ ball of radius epsilon
+pub fn pgd(
+    model: &Model,
+    images: Vec<Vec<f64>>,
+    targets: &[u8],
+    epsilon: f64,
+    steps: usize,
+) -> Vec<Vec<f64>> {
+    let original = to_array2(images);
+    let step_size = 2.5 * epsilon / steps.max(1) as f64;
+    let mut res = original.clone();
+    for _ in 0..steps {
+        let gradients = loss_gradients(model, &res, targets);
+        res = res + gradients.mapv(f64::signum) * step_size;
+        res.zip_mut_with(&original, |x, o| {
+            *x = x.clamp(o - epsilon, o + epsilon).clamp(0.0, 1.0)
+        });
+    }
+    res.rows().into_iter().map(|x| x.to_vec()).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adversary {
+    pub attack: Attack,
+    pub epsilon: f64,
+    // iterations of pgd
+    pub steps: usize,
+}
+
+impl Adversary {
+    pub fn new(attack: Attack, epsilon: f64) -> Self {
+        Self {
+            attack,
+            epsilon,
+            steps: 10,
+        }
+    }
+
+    pub fn perturb(&self, model: &Model, images: Vec<Vec<f64>>, targets: &[u8]) -> Vec<Vec<f64>> {
+        match self.attack {
+            Attack::Fgsm => fgsm(model, images, targets, self.epsilon),
+            Attack::Pgd => pgd(model, images, targets, self.epsilon, self.steps),
+        }
+    }
+
+    // trains on the batch together with its adversarial copies
+    pub fn train_handler(
+        &self,
+        chunk: &[DataSingle],
+        model: &mut Model,
+        batch_size: usize,
+    ) -> (f64, f64) {
+        let (images, targets) = split_chunk(chunk);
+        let accuracy = model
+            .infer2d(images.clone())
+            .into_iter()
+            .zip(targets.iter())
+            .filter(|(x, y)| x == *y)
+            .count() as f64
+            / batch_size as f64;
+        let adversarial = self.perturb(model, images.clone(), &targets);
+        let loss = model.train2d(
+            images.into_iter().chain(adversarial).collect(),
+            targets.iter().chain(targets.iter()).copied().collect(),
+        );
+        (loss, accuracy)
+    }
+}
+
+// accuracy under attack for each epsilon
+pub fn robustness(
+    model: &Model,
+    images: Vec<Vec<f64>>,
+    targets: &[u8],
+    attack: Attack,
+    epsilons: &[f64],
+    steps: usize,
+) -> Vec<RobustnessPoint> {
+    epsilons
+        .iter()
+        .map(|epsilon| {
+            let adversary = Adversary {
+                attack,
+                epsilon: *epsilon,
+                steps,
+            };
+            let perturbed = adversary.perturb(model, images.clone(), targets);
+            let correct = model
+                .infer2d(perturbed)
+                .iter()
+                .zip(targets)
+                .filter(|(x, y)| x == y)
+                .count();
+            RobustnessPoint {
+                epsilon: *epsilon,
+                accuracy: correct as f64 / targets.len() as f64,
+            }
+        })
+        .collect()
+}
+
+// the smallest of the epsilons whose pgd perturbation changes the prediction
+pub fn flip(model: &Model, image: &[f64], epsilons: &[f64]) -> Option<(f64, Vec<f64>)> {
+    let class = model.infer1d(image.to_vec());
+    epsilons.iter().find_map(|epsilon| {
+        let perturbed = pgd(model, vec![image.to_vec()], &[class], *epsilon, 20).remove(0);
+        (model.infer1d(perturbed.clone()) != class).then_some((*epsilon, perturbed))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn setup() -> (Model, Vec<Vec<f64>>, Vec<u8>) {
+        let mut rng = StdRng::seed_from_u64(5);
+        let model = Model::random_with(16, 8, default_classes(3), (0.1, 0.1), &mut rng);
+        let images = crate::util::random_dist_with(12, 16, &mut rng)
+            .into_iter()
+            .map(|x| x.iter().map(|v| (v + 1.0) / 2.0).collect())
+            .collect::<Vec<Vec<f64>>>();
+        let targets = model.infer2d(images.clone());
+        (model, images, targets)
+    }
+
+    fn max_change(a: &[Vec<f64>], b: &[Vec<f64>]) -> f64 {
+        a.iter()
+            .flatten()
+            .zip(b.iter().flatten())
+            .fold(0.0, |m, (x, y)| m.max((x - y).abs()))
+    }
+
+    #[test]
+    fn test_budget() {
+        let (model, images, targets) = setup();
+        let res = fgsm(&model, images.clone(), &targets, 0.1);
+        assert!(max_change(&res, &images) <= 0.1 + 1e-12);
+        let res = pgd(&model, images.clone(), &targets, 0.05, 10);
+        assert!(max_change(&res, &images) <= 0.05 + 1e-12);
+        assert!(res.iter().flatten().all(|x| (0.0..=1.0).contains(x)));
+    }
+
+    #[test]
+    fn test_robustness() {
+        let (model, images, targets) = setup();
+        let report = robustness(&model, images, &targets, Attack::Pgd, &[0.0, 0.5], 10);
+        assert_eq!(report[0].accuracy, 1.0);
+        assert!(report[1].accuracy < 1.0);
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Attack::from_name("FGSM"), Some(Attack::Fgsm));
+        assert_eq!(Attack::from_name("p-g_d"), Some(Attack::Pgd));
+        assert_eq!(Attack::from_name("cw"), None);
+    }
+
+    #[test]
+    fn test_flip() {
+        let (model, images, _) = setup();
+        let (epsilon, perturbed) = flip(&model, &images[0], &[0.01, 0.1, 0.5, 1.0]).unwrap();
+        assert!(max_change(std::slice::from_ref(&perturbed), &images[..1]) <= epsilon + 1e-12);
+        assert_ne!(model.infer1d(perturbed), model.infer1d(images[0].clone()));
+    }
+
+    #[test]
+    fn test_adversarial_training() {
+        let (mut model, images, targets) = setup();
+        let chunk = images
+            .into_iter()
+            .zip(targets)
+            .map(|(image, target)| DataSingle { target, image })
+            .collect::<Vec<DataSingle>>();
+        let (_, accuracy) = Adversary::new(Attack::Fgsm, 0.1).train_handler(&chunk, &mut model, 12);
+        assert_eq!(accuracy, 1.0);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::activations::ActivationFunctions;
+use crate::model::Model;
+use crate::util::{argmax, to_array2};
+use ndarray::{Array2, Axis};
+use serde_derive::{Deserialize, Serialize};
+
+// the range of temperatures searched, as logs
+const LOG_TEMPERATURE_RANGE: (f64, f64) = (-3.0, 3.0);
+const SEARCH_STEPS: usize = 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bin {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: usize,
+    // mean top probability and the fraction correct of the predictions in the bin
+    pub confidence: f64,
+    pub accuracy: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Calibration {
+    pub temperature: f64,
+    pub nll_before: f64,
+    pub nll_after: f64,
+    pub ece_before: f64,
+    pub ece_after: f64,
+    pub reliability_before: Vec<Bin>,
+    pub reliability_after: Vec<Bin>,
+}
+
+fn logits(model: &Model, images: Vec<Vec<f64>>) -> Array2<f64> {
+    model.forward_layers(to_array2(images).view()).1
+}
+
+fn scaled_probabilities(logits: &Array2<f64>, temperature: f64) -> Array2<f64> {
+    ActivationFunctions::logsoftmax2d(logits / temperature).mapv(f64::exp)
+}
+
+// softmax of the logits divided by the temperature, one row per image
+pub fn probabilities(model: &Model, images: Vec<Vec<f64>>, temperature: f64) -> Vec<Vec<f64>> {
+    scaled_probabilities(&logits(model, images), temperature)
+        .rows()
+        .into_iter()
+        .map(|x| x.to_vec())
+        .collect()
+}
+
+fn nll(logits: &Array2<f64>, targets: &[u8], temperature: f64) -> f64 {
+    let log_probabilities = ActivationFunctions::logsoftmax2d(logits / temperature);
+    -targets
+        .iter()
+        .enumerate()
+        .map(|(i, t)| log_probabilities[[i, *t as usize]])
+        .sum::<f64>()
+        / targets.len() as f64
+}
+
+// the temperature minimising the negative log likelihood, which is unimodal in log t
+pub fn fit_temperature(model: &Model, images: Vec<Vec<f64>>, targets: &[u8]) -> f64 {
+    let logits = logits(model, images);
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = LOG_TEMPERATURE_RANGE;
+    for _ in 0..SEARCH_STEPS {
+        let a = high - ratio * (high - low);
+        let b = low + ratio * (high - low);
+        if nll(&logits, targets, a.exp()) < nll(&logits, targets, b.exp()) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    ((low + high) / 2.0).exp()
+}
+
+// predictions grouped into equal width bins of their top probability
+pub fn reliability(probabilities: &[Vec<f64>], targets: &[u8], bins: usize) -> Vec<Bin> {
+    let mut sums = vec![(0, 0.0, 0); bins];
+    for (row, target) in probabilities.iter().zip(targets) {
+        let prediction = argmax(row.iter());
+        let confidence = row[prediction as usize];
+        let bin = ((confidence * bins as f64) as usize).min(bins - 1);
+        sums[bin].0 += 1;
+        sums[bin].1 += confidence;
+        sums[bin].2 += (prediction == *target) as usize;
+    }
+    sums.into_iter()
+        .enumerate()
+        .map(|(i, (count, confidence, correct))| Bin {
+            lower: i as f64 / bins as f64,
+            upper: (i + 1) as f64 / bins as f64,
+            count,
+            confidence: confidence / count.max(1) as f64,
+            accuracy: correct as f64 / count.max(1) as f64,
+        })
+        .collect()
+}
+
+// expected calibration error: the gap between confidence and accuracy, weighted by bin size
+pub fn ece(bins: &[Bin]) -> f64 {
+    let total = bins.iter().map(|x| x.count).sum::<usize>().max(1) as f64;
+    bins.iter()
+        .map(|x| x.count as f64 / total * (x.confidence - x.accuracy).abs())
+        .sum()
+}
+
+pub fn calibrate(model: &Model, images: Vec<Vec<f64>>, targets: &[u8], bins: usize) -> Calibration {
+    let temperature = fit_temperature(model, images.clone(), targets);
+    let logits = logits(model, images);
+    let rows = |t: f64| {
+        scaled_probabilities(&logits, t)
+            .axis_iter(Axis(0))
+            .map(|x| x.to_vec())
+            .collect::<Vec<Vec<f64>>>()
+    };
+    let reliability_before = reliability(&rows(1.0), targets, bins);
+    let reliability_after = reliability(&rows(temperature), targets, bins);
+    Calibration {
+        temperature,
+        nll_before: nll(&logits, targets, 1.0),
+        nll_after: nll(&logits, targets, temperature),
+        ece_before: ece(&reliability_before),
+        ece_after: ece(&reliability_after),
+        reliability_before,
+        reliability_after,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use crate::util::approximate_equal;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_probabilities() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let model = Model::random_with(4, 3, default_classes(3), (0.1, 0.1), &mut rng);
+        let images = vec![vec![0.2, 0.4, 0.6, 0.8]];
+        let plain = probabilities(&model, images.clone(), 1.0);
+        let flat = probabilities(&model, images.clone(), 1000.0);
+        assert!(approximate_equal(plain[0].iter().sum(), 1.0, None));
+        let log_probabilities = &model.activations2d(images)[0].log_probabilities;
+        for (p, l) in plain[0].iter().zip(log_probabilities) {
+            assert!(approximate_equal(*p, l.exp(), None));
+        }
+        assert!(flat[0].iter().all(|x| (x - 1.0 / 3.0).abs() < 1e-3));
+    }
+
+    #[test]
+    fn test_fit_temperature() {
+        // a model that is right 70% of the time but very sure of itself
+        let mut rng = StdRng::seed_from_u64(2);
+        let model = Model::new(
+            (
+                vec![vec![1.0, 0.0], vec![0.0, 1.0]],
+                vec![vec![8.0, 0.0], vec![0.0, 8.0]],
+            ),
+            (0.1, 0.1),
+        );
+        let (images, targets): (Vec<Vec<f64>>, Vec<u8>) = (0..500)
+            .map(|i| {
+                let target = (i % 2) as u8;
+                let shown = if rng.gen::<f64>() < 0.7 {
+                    target
+                } else {
+                    1 - target
+                };
+                let mut image = vec![0.0; 2];
+                image[shown as usize] = rng.gen_range(0.5..1.0);
+                (image, target)
+            })
+            .unzip();
+        let report = calibrate(&model, images, &targets, 10);
+        assert!(report.temperature > 1.0);
+        assert!(report.nll_after < report.nll_before);
+        assert!(report.ece_after < report.ece_before);
+        assert_eq!(report.reliability_after.len(), 10);
+        assert_eq!(
+            report
+                .reliability_after
+                .iter()
+                .map(|x| x.count)
+                .sum::<usize>(),
+            500
+        );
+    }
+
+    #[test]
+    fn test_ece() {
+        let probabilities = vec![vec![0.9, 0.1], vec![0.9, 0.1], vec![0.4, 0.6]];
+        let bins = reliability(&probabilities, &[0, 1, 1], 10);
+        assert_eq!(bins[9].count, 2);
+        assert!(approximate_equal(bins[9].accuracy, 0.5, None));
+        // (2 * |0.9 - 0.5| + |0.6 - 1|) / 3
+        assert!(approximate_equal(ece(&bins), 0.4, None));
+    }
+}
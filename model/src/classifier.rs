@@ -0,0 +1,267 @@
+use crate::knn::Knn;
+use crate::logistic::{LogisticRegression, LogisticWeights};
+use crate::model::Model;
+use crate::util::{argmax, get_sample_block_with, train_handler, Data, Weights};
+use rand::{rngs::StdRng, SeedableRng};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassifierKind {
+    #[default]
+    Mlp,
+    Knn,
+    LogisticRegression,
+}
+
+impl ClassifierKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace(['_', '-'], "").as_str() {
+            "mlp" | "model" => Some(Self::Mlp),
+            "knn" => Some(Self::Knn),
+            "logisticregression" | "logistic" => Some(Self::LogisticRegression),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FitOptions {
+    pub iterations: usize,
+    pub batch_size: usize,
+    pub seed: Option<u64>,
+}
+
+impl FitOptions {
+    pub(crate) fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
+
+// a trained classifier in a form that can be stored and loaded back
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SavedClassifier {
+    Mlp(Box<Weights>),
+    Knn(Knn),
+    LogisticRegression(LogisticWeights),
+}
+
+impl SavedClassifier {
+    // ready for prediction; an mlp comes back with zero learning rates
+    pub fn load(self) -> Box<dyn Classifier> {
+        match self {
+            Self::Mlp(weights) => Box::new(Model::from_weights(*weights, (0.0, 0.0))),
+            Self::Knn(knn) => Box::new(knn),
+            Self::LogisticRegression(weights) => {
+                Box::new(LogisticRegression::from_weights(weights))
+            }
+        }
+    }
+}
+
+pub trait Classifier {
+    fn kind(&self) -> ClassifierKind;
+
+    fn fit(&mut self, data: &Data, options: &FitOptions);
+
+    // one row of class probabilities per image
+    fn predict_proba(&self, images: Vec<Vec<f64>>) -> Vec<Vec<f64>>;
+
+    fn predict(&self, images: Vec<Vec<f64>>) -> Vec<u8> {
+        self.predict_proba(images)
+            .iter()
+            .map(|x| argmax(x.iter()))
+            .collect()
+    }
+
+    fn serialise(&self) -> SavedClassifier;
+}
+
+impl Classifier for Model {
+    fn kind(&self) -> ClassifierKind {
+        ClassifierKind::Mlp
+    }
+
+    fn fit(&mut self, data: &Data, options: &FitOptions) {
+        let mut rng = options.rng();
+        for _ in 0..options.iterations {
+            let chunk = get_sample_block_with(data, options.batch_size, &mut rng);
+            train_handler(&chunk, self, options.batch_size);
+        }
+    }
+
+    fn predict_proba(&self, images: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        self.activations2d(images)
+            .into_iter()
+            .map(|x| x.log_probabilities.iter().map(|p| p.exp()).collect())
+            .collect()
+    }
+
+    fn predict(&self, images: Vec<Vec<f64>>) -> Vec<u8> {
+        self.infer2d(images)
+    }
+
+    fn serialise(&self) -> SavedClassifier {
+        SavedClassifier::Mlp(Box::new(self.export()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClassMetrics {
+    pub class: String,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    pub support: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub accuracy: f64,
+    // mean negative log of the probability given to the true class
+    pub log_loss: f64,
+    pub macro_f1: f64,
+    pub classes: Vec<ClassMetrics>,
+    // rows are the true class and columns the predicted one
+    pub confusion: Vec<Vec<usize>>,
+}
+
+// the same report for any classifier
+pub fn evaluate(
+    classifier: &dyn Classifier,
+    images: Vec<Vec<f64>>,
+    targets: &[u8],
+    classes: &[String],
+) -> Metrics {
+    let probabilities = classifier.predict_proba(images);
+    let mut confusion = vec![vec![0; classes.len()]; classes.len()];
+    let mut log_loss = 0.0;
+    for (row, target) in probabilities.iter().zip(targets) {
+        confusion[*target as usize][argmax(row.iter()) as usize] += 1;
+        log_loss -= row[*target as usize].clamp(1e-12, 1.0).ln();
+    }
+    let metrics = classes
+        .iter()
+        .enumerate()
+        .map(|(i, class)| {
+            let correct = confusion[i][i] as f64;
+            let predicted = confusion.iter().map(|x| x[i]).sum::<usize>() as f64;
+            let support = confusion[i].iter().sum::<usize>();
+            let precision = correct / predicted.max(1.0);
+            let recall = correct / (support as f64).max(1.0);
+            let f1 = match precision + recall > 0.0 {
+                true => 2.0 * precision * recall / (precision + recall),
+                false => 0.0,
+            };
+            ClassMetrics {
+                class: class.clone(),
+                precision,
+                recall,
+                f1,
+                support,
+            }
+        })
+        .collect::<Vec<ClassMetrics>>();
+    let total = targets.len().max(1) as f64;
+    Metrics {
+        accuracy: (0..classes.len()).map(|i| confusion[i][i]).sum::<usize>() as f64 / total,
+        log_loss: log_loss / total,
+        macro_f1: metrics.iter().map(|x| x.f1).sum::<f64>() / metrics.len().max(1) as f64,
+        classes: metrics,
+        confusion,
+    }
+}
+
+// for tests of each classifier: two classes lighting up opposite halves of a 16 pixel image
+#[cfg(test)]
+pub(crate) fn halves(count: usize, seed: u64) -> Data {
+    use crate::util::DataSingle;
+    use rand::Rng;
+    let mut rng = StdRng::seed_from_u64(seed);
+    Data {
+        data: (0..count)
+            .map(|i| {
+                let target = (i % 2) as u8;
+                let image = (0..16)
+                    .map(|j| match (j < 8) == (target == 0) {
+                        true => rng.gen_range(0.6..1.0),
+                        false => rng.gen_range(0.0..0.1),
+                    })
+                    .collect();
+                DataSingle { image, target }
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use crate::util::{approximate_equal, split_chunk};
+
+    #[test]
+    fn test_classifiers() {
+        let train = halves(200, 1);
+        let (images, targets) = split_chunk(&halves(100, 2).data);
+        let options = FitOptions {
+            iterations: 200,
+            batch_size: 16,
+            seed: Some(3),
+        };
+        let classifiers: Vec<Box<dyn Classifier>> = vec![
+            Box::new(Model::random(16, 8, default_classes(2), (0.1, 0.1))),
+            Box::new(Knn::new(5, 0.5, 2)),
+            Box::new(LogisticRegression::new(16, 2, 0.1, 0.0)),
+        ];
+        for mut classifier in classifiers {
+            classifier.fit(&train, &options);
+            let metrics = evaluate(
+                classifier.as_ref(),
+                images.clone(),
+                &targets,
+                &default_classes(2),
+            );
+            assert!(
+                metrics.accuracy > 0.9,
+                "{:?} {}",
+                classifier.kind(),
+                metrics.accuracy
+            );
+            let loaded = classifier.serialise().load();
+            assert_eq!(loaded.kind(), classifier.kind());
+            assert_eq!(
+                loaded.predict(images.clone()),
+                classifier.predict(images.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        struct Fixed;
+        impl Classifier for Fixed {
+            fn kind(&self) -> ClassifierKind {
+                ClassifierKind::Mlp
+            }
+            fn fit(&mut self, _: &Data, _: &FitOptions) {}
+            fn predict_proba(&self, images: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+                images
+            }
+            fn serialise(&self) -> SavedClassifier {
+                SavedClassifier::Knn(Knn::new(1, 0.5, 2))
+            }
+        }
+        let images = vec![vec![0.9, 0.1], vec![0.2, 0.8], vec![0.6, 0.4]];
+        let metrics = evaluate(&Fixed, images, &[0, 1, 1], &default_classes(2));
+        assert!(approximate_equal(metrics.accuracy, 2.0 / 3.0, None));
+        assert_eq!(metrics.confusion, vec![vec![1, 0], vec![1, 1]]);
+        assert!(approximate_equal(metrics.classes[0].precision, 0.5, None));
+        assert!(approximate_equal(metrics.classes[1].recall, 0.5, None));
+        assert_eq!(metrics.classes[1].support, 2);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::preprocess;
+use crate::util::Data;
+use std::io::{Error, ErrorKind, Result};
+
+const FASHION_MNIST: [&str; 10] = [
+    "T-shirt/top",
+    "Trouser",
+    "Pullover",
+    "Dress",
+    "Coat",
+    "Sandal",
+    "Shirt",
+    "Sneaker",
+    "Bag",
+    "Ankle boot",
+];
+
+// labels 10 to 14 after the digits
+pub const OPERATORS: [&str; 5] = ["+", "−", "×", "÷", "="];
+
+const EMNIST_BALANCED_LOWER: [char; 11] = ['a', 'b', 'd', 'e', 'f', 'g', 'h', 'n', 'q', 'r', 't'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dataset {
+    Mnist,
+    FashionMnist,
+    EmnistDigits,
+    EmnistLetters,
+    EmnistBalanced,
+    // digits and arithmetic operators, supplied as csv
+    Arithmetic,
+}
+
+impl Dataset {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace(['_', '-'], "").as_str() {
+            "mnist" => Some(Self::Mnist),
+            "fashionmnist" | "fashion" => Some(Self::FashionMnist),
+            "emnistdigits" => Some(Self::EmnistDigits),
+            "emnistletters" => Some(Self::EmnistLetters),
+            "emnistbalanced" => Some(Self::EmnistBalanced),
+            "arithmetic" | "math" => Some(Self::Arithmetic),
+            _ => None,
+        }
+    }
+
+    pub fn classes(&self) -> Vec<String> {
+        match self {
+            Self::Mnist | Self::EmnistDigits => default_classes(10),
+            Self::FashionMnist => FASHION_MNIST.iter().map(|x| x.to_string()).collect(),
+            Self::EmnistLetters => ('A'..='Z').map(|x| x.to_string()).collect(),
+            Self::EmnistBalanced => ('0'..='9')
+                .chain('A'..='Z')
+                .chain(EMNIST_BALANCED_LOWER)
+                .map(|x| x.to_string())
+                .collect(),
+            Self::Arithmetic => default_classes(10)
+                .into_iter()
+                .chain(OPERATORS.iter().map(|x| x.to_string()))
+                .collect(),
+        }
+    }
+
+    // EMNIST letters are labelled from 1 and all EMNIST images are stored transposed
+    pub fn label_offset(&self) -> u8 {
+        match self {
+            Self::EmnistLetters => 1,
+            _ => 0,
+        }
+    }
+
+    pub fn transposed(&self) -> bool {
+        matches!(
+            self,
+            Self::EmnistDigits | Self::EmnistLetters | Self::EmnistBalanced
+        )
+    }
+
+    // classes is the number of outputs the labels have to fit, which CLASSES can change
+    pub fn prepare(&self, mut data: Data, classes: usize) -> Result<Data> {
+        for (row, single) in data.data.iter_mut().enumerate() {
+            single.target = single
+                .target
+                .checked_sub(self.label_offset())
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "row {} has label {} below the first label {}",
+                            row,
+                            single.target,
+                            self.label_offset()
+                        ),
+                    )
+                })?;
+            if single.target as usize >= classes {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "row {} has label {} past the last of {} classes",
+                        row,
+                        single.target as usize + self.label_offset() as usize,
+                        classes
+                    ),
+                ));
+            }
+            if self.transposed() {
+                single.image = preprocess::transpose(&single.image);
+            }
+        }
+        Ok(data)
+    }
+}
+
+pub fn default_classes(n: usize) -> Vec<String> {
+    (0..n).map(|x| x.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::DataSingle;
+
+    #[test]
+    fn test_classes() {
+        assert_eq!(Dataset::Mnist.classes().len(), 10);
+        assert_eq!(Dataset::FashionMnist.classes()[9], "Ankle boot");
+        assert_eq!(Dataset::EmnistLetters.classes().len(), 26);
+        assert_eq!(Dataset::EmnistBalanced.classes().len(), 47);
+        assert_eq!(Dataset::EmnistBalanced.classes()[36], "a");
+        assert_eq!(Dataset::Arithmetic.classes().len(), 15);
+        assert_eq!(Dataset::Arithmetic.classes()[12], "×");
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            Dataset::from_name("fashion-mnist"),
+            Some(Dataset::FashionMnist)
+        );
+        assert_eq!(
+            Dataset::from_name("EMNIST_LETTERS"),
+            Some(Dataset::EmnistLetters)
+        );
+        assert_eq!(Dataset::from_name("cifar"), None);
+    }
+
+    #[test]
+    fn test_prepare() {
+        let data = Data {
+            data: vec![DataSingle {
+                target: 1,
+                image: vec![1.0, 2.0, 3.0, 4.0],
+            }],
+        };
+        let res = Dataset::EmnistLetters.prepare(data.clone(), 26).unwrap();
+        assert_eq!(res.data[0].target, 0);
+        assert_eq!(res.data[0].image, vec![1.0, 3.0, 2.0, 4.0]);
+        assert_eq!(Dataset::Mnist.prepare(data.clone(), 10).unwrap(), data);
+    }
+
+    #[test]
+    fn test_prepare_label_below_offset() {
+        let data = Data {
+            data: vec![DataSingle {
+                target: 0,
+                image: vec![0.0; 4],
+            }],
+        };
+        let err = Dataset::EmnistLetters.prepare(data, 26).unwrap_err();
+        assert!(err.to_string().contains("row 0"));
+    }
+
+    #[test]
+    fn test_prepare_label_past_classes() {
+        let data = |target: u8| Data {
+            data: vec![
+                DataSingle {
+                    target: 1,
+                    image: vec![0.0; 4],
+                },
+                DataSingle {
+                    target,
+                    image: vec![0.0; 4],
+                },
+            ],
+        };
+        assert!(Dataset::Mnist.prepare(data(9), 10).is_ok());
+        let err = Dataset::Mnist.prepare(data(10), 10).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("row 1 has label 10"));
+        let err = Dataset::EmnistLetters.prepare(data(27), 26).unwrap_err();
+        assert!(err.to_string().contains("row 1 has label 27"));
+    }
+}
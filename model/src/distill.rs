@@ -0,0 +1,138 @@
+use crate::activations::ActivationFunctions;
+use crate::model::{map_chunks, Model};
+use crate::util::{one_hot, split_chunk, to_array2, DataSingle};
+use ndarray::{Array2, ArrayView2};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Distillation {
+    pub temperature: f64,
+    // weight of the hard label loss, the softened teacher loss gets the rest
+    pub alpha: f64,
+}
+
+impl Distillation {
+    pub fn new(temperature: f64, alpha: f64) -> Self {
+        Self { temperature, alpha }
+    }
+
+    pub fn train2d(
+        &self,
+        student: &mut Model,
+        teacher: &Model,
+        input: Vec<Vec<f64>>,
+        target: Vec<u8>,
+    ) -> f64 {
+        assert_eq!(student.num_classes(), teacher.num_classes());
+        let input = to_array2(input);
+        let target_len = target.len();
+        let (loss, gradients) = map_chunks(&input, |chunk, start| {
+            self.backward2d(
+                student,
+                teacher,
+                chunk,
+                &target[start..start + chunk.nrows()],
+                target_len,
+            )
+        })
+        .into_iter()
+        .reduce(|(loss_a, grad_a), (loss_b, grad_b)| {
+            (loss_a + loss_b, (grad_a.0 + grad_b.0, grad_a.1 + grad_b.1))
+        })
+        .unwrap();
+        student.update_weights(gradients);
+        loss / target_len as f64
+    }
+
+    fn backward2d(
+        &self,
+        student: &Model,
+        teacher: &Model,
+        input: ArrayView2<f64>,
+        target: &[u8],
+        target_len: usize,
+    ) -> (f64, (Array2<f64>, Array2<f64>)) {
+        let t = self.temperature;
+        let (_, teacher_logits) = teacher.forward_layers(input);
+        let soft_targets = ActivationFunctions::logsoftmax2d(teacher_logits / t).mapv(f64::exp);
+        let (layer1_relu, logits) = student.forward_layers(input);
+        let hard = ActivationFunctions::logsoftmax2d(logits.clone());
+        let soft = ActivationFunctions::logsoftmax2d(logits / t);
+        let target = one_hot(target, student.num_classes());
+
+        let hard_loss = -(&target * &hard).sum();
+        let soft_entropy = soft_targets.mapv(|p| if p > 0.0 { p * p.ln() } else { 0.0 });
+        // scaled by t^2 so the soft gradients keep their magnitude as t changes
+        let soft_loss = (soft_entropy - &soft_targets * &soft).sum() * t * t;
+        let loss = self.alpha * hard_loss + (1.0 - self.alpha) * soft_loss;
+
+        let hard_gradients = hard.mapv(f64::exp) - &target;
+        let soft_gradients = (soft.mapv(f64::exp) - &soft_targets) * t;
+        let logit_gradients =
+            (hard_gradients * self.alpha + soft_gradients * (1.0 - self.alpha)) / target_len as f64;
+        (
+            loss,
+            student.backward_layers(input, layer1_relu, logit_gradients),
+        )
+    }
+
+    pub fn train_handler(
+        &self,
+        chunk: &[DataSingle],
+        student: &mut Model,
+        teacher: &Model,
+    ) -> (f64, f64) {
+        let (images, targets) = split_chunk(chunk);
+        let accuracy = student
+            .infer2d(images.clone())
+            .into_iter()
+            .zip(targets.iter())
+            .filter(|(x, y)| x == *y)
+            .count() as f64
+            / chunk.len() as f64;
+        let loss = self.train2d(student, teacher, images, targets);
+        (loss, accuracy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use crate::util::{approximate_equal, random_dist, random_int};
+
+    #[test]
+    fn test_hard_labels_match_train2d() {
+        let input = random_dist(40, 784);
+        let target = random_int(1, 40, 10)[0].clone();
+        let teacher = Model::random(784, 64, default_classes(10), (0.1, 0.1));
+        let mut student = Model::random(784, 16, default_classes(10), (0.1, 0.1));
+        let mut expected = student.clone();
+
+        Distillation::new(3.0, 1.0).train2d(&mut student, &teacher, input.clone(), target.clone());
+        expected.train2d(input, target);
+        assert!(student
+            .weights
+            .1
+            .iter()
+            .zip(expected.weights.1.iter())
+            .all(|(x, y)| approximate_equal(*x, *y, Some(1e-9))));
+    }
+
+    #[test]
+    fn test_student_follows_teacher() {
+        let input = random_dist(64, 784)
+            .into_iter()
+            .map(|x| x.iter().map(|v| v * 0.05).collect())
+            .collect::<Vec<Vec<f64>>>();
+        let target = random_int(1, 64, 10)[0].clone();
+        let teacher = Model::random(784, 64, default_classes(10), (0.0, 0.0));
+        let mut student = Model::random(784, 16, default_classes(10), (0.01, 0.01));
+        let distillation = Distillation::new(2.0, 0.0);
+        let first = distillation.train2d(&mut student, &teacher, input.clone(), target.clone());
+        let mut last = first;
+        for _ in 0..30 {
+            last = distillation.train2d(&mut student, &teacher, input.clone(), target.clone());
+        }
+        assert!(last < first);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::model::{map_chunks, Model};
+use crate::util::{argmax, to_array2};
+use ndarray::{Array2, ArrayView2, Axis};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Combine {
+    #[default]
+    LogProbabilities,
+    Probabilities,
+    Vote,
+}
+
+#[derive(Clone, Debug)]
+pub struct Ensemble {
+    pub members: Vec<Model>,
+    pub member_weights: Vec<f64>,
+    pub combine: Combine,
+}
+
+impl Ensemble {
+    pub fn new(members: Vec<Model>, combine: Combine) -> Self {
+        let member_weights = vec![1.0; members.len()];
+        Self::with_weights(members, member_weights, combine)
+    }
+
+    pub fn with_weights(members: Vec<Model>, member_weights: Vec<f64>, combine: Combine) -> Self {
+        assert!(!members.is_empty());
+        assert_eq!(members.len(), member_weights.len());
+        assert!(members
+            .iter()
+            .all(|x| x.num_classes() == members[0].num_classes()
+                && x.input_size() == members[0].input_size()));
+        Self {
+            members,
+            member_weights,
+            combine,
+        }
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.members[0].input_size()
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.members[0].num_classes()
+    }
+
+    pub fn class_name(&self, class: u8) -> String {
+        self.members[0].class_name(class)
+    }
+
+    pub fn infer1d(&self, input: Vec<f64>) -> u8 {
+        self.infer2d(vec![input])[0]
+    }
+
+    pub fn infer2d(&self, input: Vec<Vec<f64>>) -> Vec<u8> {
+        let input = to_array2(input);
+        map_chunks(&input, |chunk, _| {
+            self.scores(chunk)
+                .axis_iter(Axis(0))
+                .map(|x| argmax(x.iter()))
+                .collect::<Vec<u8>>()
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    // weighted per-class scores, the prediction is the highest score
+    fn scores(&self, input: ArrayView2<f64>) -> Array2<f64> {
+        let mut res = Array2::zeros((input.nrows(), self.num_classes()));
+        for (member, weight) in self.members.iter().zip(self.member_weights.iter()) {
+            let log_probabilities = member.log_probabilities(input);
+            match self.combine {
+                Combine::LogProbabilities => res = res + log_probabilities * *weight,
+                Combine::Probabilities => res = res + log_probabilities.mapv(f64::exp) * *weight,
+                Combine::Vote => {
+                    for (i, row) in log_probabilities.axis_iter(Axis(0)).enumerate() {
+                        res[[i, argmax(row.iter()) as usize]] += weight;
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use crate::util::random_dist;
+
+    fn model() -> Model {
+        Model::random(784, 32, default_classes(10), (0.1, 0.1))
+    }
+
+    #[test]
+    fn test_single_member() {
+        let member = model();
+        let input = random_dist(40, 784);
+        for combine in [
+            Combine::LogProbabilities,
+            Combine::Probabilities,
+            Combine::Vote,
+        ] {
+            let ensemble = Ensemble::new(vec![member.clone()], combine);
+            assert_eq!(
+                ensemble.infer2d(input.clone()),
+                member.infer2d(input.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_member_weights() {
+        let members = vec![model(), model(), model()];
+        let input = random_dist(40, 784);
+        let ensemble = Ensemble::with_weights(members.clone(), vec![0.0, 1.0, 0.0], Combine::Vote);
+        assert_eq!(
+            ensemble.infer2d(input.clone()),
+            members[1].infer2d(input.clone())
+        );
+
+        let ensemble = Ensemble::new(members.clone(), Combine::Vote);
+        let predictions = members
+            .iter()
+            .map(|x| x.infer2d(input.clone()))
+            .collect::<Vec<Vec<u8>>>();
+        for (i, prediction) in ensemble.infer2d(input.clone()).iter().enumerate() {
+            let votes = predictions.iter().filter(|x| x[i] == *prediction).count();
+            assert!(predictions.iter().all(|x| predictions
+                .iter()
+                .filter(|y| y[i] == x[i])
+                .count()
+                <= votes));
+        }
+        assert_eq!(
+            ensemble.infer1d(input[0].clone()),
+            ensemble.infer2d(input)[0]
+        );
+    }
+}
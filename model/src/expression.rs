@@ -0,0 +1,203 @@
+use crate::model::Model;
+use crate::ood::OodDetector;
+use crate::segment::{self, Reading, Segmentation};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Number(f64),
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equals,
+}
+
+// accepts the operator classes and their ascii forms
+pub fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut res = Vec::new();
+    let mut digits = String::new();
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        if !digits.is_empty() {
+            res.push(Token::Number(digits.parse::<f64>().unwrap()));
+            digits.clear();
+        }
+        res.push(match c {
+            '+' => Token::Add,
+            '-' | '−' => Token::Subtract,
+            '*' | 'x' | '×' => Token::Multiply,
+            '/' | '÷' => Token::Divide,
+            '=' => Token::Equals,
+            _ => return Err(format!("unexpected {}", c)),
+        });
+    }
+    if !digits.is_empty() {
+        res.push(Token::Number(digits.parse::<f64>().unwrap()));
+    }
+    Ok(res)
+}
+
+// recursive descent over expr := term (+|- term)*, term := factor (×|÷ factor)*,
+// factor := -factor | number
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1)
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        let mut res = self.term()?;
+        while let Some(op) = self.peek().cloned() {
+            match op {
+                Token::Add => res += self.skip().term()?,
+                Token::Subtract => res -= self.skip().term()?,
+                _ => break,
+            }
+        }
+        Ok(res)
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let mut res = self.factor()?;
+        while let Some(op) = self.peek().cloned() {
+            match op {
+                Token::Multiply => res *= self.skip().factor()?,
+                Token::Divide => {
+                    let divisor = self.skip().factor()?;
+                    if divisor == 0.0 {
+                        return Err("division by zero".to_string());
+                    }
+                    res /= divisor;
+                }
+                _ => break,
+            }
+        }
+        Ok(res)
+    }
+
+    fn factor(&mut self) -> Result<f64, String> {
+        match self.next() {
+            Some(Token::Number(x)) => Ok(*x),
+            Some(Token::Subtract) => Ok(-self.factor()?),
+            Some(token) => Err(format!("expected a number, found {:?}", token)),
+            None => Err("expected a number, found the end".to_string()),
+        }
+    }
+
+    fn skip(&mut self) -> &mut Self {
+        self.pos += 1;
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    pub value: f64,
+    // what was written after an =, and whether it matches
+    pub claimed: Option<f64>,
+    pub correct: Option<bool>,
+}
+
+pub fn evaluate(text: &str) -> Result<Evaluation, String> {
+    let tokens = tokenize(text)?;
+    let (left, right) = match tokens.iter().position(|x| *x == Token::Equals) {
+        Some(idx) => (&tokens[..idx], Some(&tokens[idx + 1..])),
+        None => (&tokens[..], None),
+    };
+    let value = parse(left)?;
+    let claimed = match right {
+        Some(right) if !right.is_empty() => Some(parse(right)?),
+        _ => None,
+    };
+    Ok(Evaluation {
+        value,
+        claimed,
+        correct: claimed.map(|x| (x - value).abs() < 1e-9),
+    })
+}
+
+fn parse(tokens: &[Token]) -> Result<f64, String> {
+    let mut parser = Parser { tokens, pos: 0 };
+    let res = parser.expression()?;
+    match parser.peek() {
+        None => Ok(res),
+        Some(token) => Err(format!("unexpected {:?}", token)),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExpressionReading {
+    pub reading: Reading,
+    pub evaluation: Option<Evaluation>,
+    pub error: Option<String>,
+}
+
+// reads the symbols on a wide canvas like segment::read, then evaluates them
+pub fn read(
+    model: &Model,
+    image: &[f64],
+    width: usize,
+    method: Segmentation,
+    temperature: f64,
+    ood: Option<&OodDetector>,
+) -> ExpressionReading {
+    let reading = segment::read(model, image, width, method, temperature, ood);
+    let (evaluation, error) = match evaluate(&reading.text) {
+        Ok(evaluation) => (Some(evaluation), None),
+        Err(error) => (None, Some(error)),
+    };
+    ExpressionReading {
+        reading,
+        evaluation,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("12+7×3").unwrap(),
+            vec![
+                Token::Number(12.0),
+                Token::Add,
+                Token::Number(7.0),
+                Token::Multiply,
+                Token::Number(3.0)
+            ]
+        );
+        assert!(tokenize("1?2").is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(evaluate("12+7*3").unwrap().value, 33.0);
+        assert_eq!(evaluate("12−4−3").unwrap().value, 5.0);
+        assert_eq!(evaluate("8÷4÷2").unwrap().value, 1.0);
+        assert_eq!(evaluate("-3×-2").unwrap().value, 6.0);
+        let checked = evaluate("2+2=5").unwrap();
+        assert_eq!(checked.claimed, Some(5.0));
+        assert_eq!(checked.correct, Some(false));
+        assert_eq!(evaluate("6÷3=").unwrap().correct, None);
+        assert!(evaluate("1÷0").is_err());
+        assert!(evaluate("1+").is_err());
+        assert!(evaluate("").is_err());
+        assert!(evaluate("×3").is_err());
+    }
+}
@@ -0,0 +1,392 @@
+use crate::model::Model;
+use crate::util::{to_array2, WeightUpdate};
+use ndarray::Array2;
+use serde_derive::{Deserialize, Serialize};
+
+type Delta = (Array2<f64>, Array2<f64>);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    #[default]
+    FedAvg,
+    Median,
+    TrimmedMean,
+    Krum,
+}
+
+impl Aggregation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace(['_', '-'], "").as_str() {
+            "fedavg" | "mean" => Some(Self::FedAvg),
+            "median" => Some(Self::Median),
+            "trimmedmean" => Some(Self::TrimmedMean),
+            "krum" => Some(Self::Krum),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Aggregator {
+    pub method: Aggregation,
+    // fraction of the values cut from each end per coordinate by the trimmed mean
+    pub trim: f64,
+    // number of malicious clients Krum tolerates
+    pub byzantine: usize,
+    pub clip_norm: Option<f64>,
+}
+
+impl Default for Aggregator {
+    fn default() -> Self {
+        Self {
+            method: Aggregation::FedAvg,
+            trim: 0.1,
+            byzantine: 1,
+            clip_norm: None,
+        }
+    }
+}
+
+impl Aggregator {
+    pub fn aggregate(&self, updates: &[WeightUpdate]) -> Delta {
+        let deltas = updates
+            .iter()
+            .map(|x| {
+                let delta = update_delta(x);
+                match self.clip_norm {
+                    Some(max) => clip(delta, max),
+                    None => delta,
+                }
+            })
+            .collect::<Vec<Delta>>();
+        match self.method {
+            Aggregation::FedAvg => weighted_mean(
+                &deltas,
+                &updates.iter().map(|x| x.samples).collect::<Vec<usize>>(),
+            ),
+            Aggregation::Median => coordinate_wise(&deltas, median),
+            Aggregation::TrimmedMean => {
+                let cut = (deltas.len() as f64 * self.trim).floor() as usize;
+                let cut = cut.min((deltas.len() - 1) / 2);
+                coordinate_wise(&deltas, |values| {
+                    values.sort_by(|x, y| x.total_cmp(y));
+                    let kept = &values[cut..values.len() - cut];
+                    kept.iter().sum::<f64>() / kept.len() as f64
+                })
+            }
+            Aggregation::Krum => deltas[krum(&deltas, self.byzantine)].clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rejection {
+    pub base_version: u64,
+    pub samples: usize,
+    pub score: f64,
+    pub accuracy_drop: Option<f64>,
+    pub reason: String,
+}
+
+pub fn weight_delta(base: &Model, local: &Model) -> (Array2<f64>, Array2<f64>) {
+    (
+        &local.weights.0 - &base.weights.0,
+        &local.weights.1 - &base.weights.1,
+    )
+}
+
+pub fn make_update(base_version: u64, base: &Model, local: &Model, samples: usize) -> WeightUpdate {
+    let delta = weight_delta(base, local);
+    let rows = |x: &Array2<f64>| x.rows().into_iter().map(|r| r.to_vec()).collect();
+    WeightUpdate {
+        base_version,
+        delta: (rows(&delta.0), rows(&delta.1)),
+        samples,
+    }
+}
+
+pub fn update_delta(update: &WeightUpdate) -> (Array2<f64>, Array2<f64>) {
+    (
+        to_array2(update.delta.0.clone()),
+        to_array2(update.delta.1.clone()),
+    )
+}
+
+pub fn matches_model(model: &Model, update: &WeightUpdate) -> bool {
+    let shape = |x: &Vec<Vec<f64>>| (x.len(), x.first().map_or(0, |r| r.len()));
+    let rectangular = |x: &Vec<Vec<f64>>| x.iter().all(|r| r.len() == shape(x).1);
+    shape(&update.delta.0) == model.weights.0.dim()
+        && shape(&update.delta.1) == model.weights.1.dim()
+        && rectangular(&update.delta.0)
+        && rectangular(&update.delta.1)
+        && update
+            .delta
+            .0
+            .iter()
+            .chain(update.delta.1.iter())
+            .flatten()
+            .all(|x| x.is_finite())
+}
+
+// FedAvg: the sample weighted mean of the client deltas
+pub fn fed_avg(updates: &[WeightUpdate]) -> (Array2<f64>, Array2<f64>) {
+    Aggregator::default().aggregate(updates)
+}
+
+fn weighted_mean(deltas: &[Delta], samples: &[usize]) -> Delta {
+    // summed as floats, since sample counts come from clients
+    let total = samples.iter().map(|x| *x as f64).sum::<f64>();
+    let weight = |x: usize| match total > 0.0 {
+        true => x as f64 / total,
+        false => 1.0 / deltas.len() as f64,
+    };
+    deltas
+        .iter()
+        .zip(samples)
+        .map(|(delta, samples)| (&delta.0 * weight(*samples), &delta.1 * weight(*samples)))
+        .reduce(|a, b| (a.0 + b.0, a.1 + b.1))
+        .unwrap()
+}
+
+fn coordinate_wise<F: Fn(&mut [f64]) -> f64>(deltas: &[Delta], f: F) -> Delta {
+    let layer = |get: fn(&Delta) -> &Array2<f64>| {
+        Array2::from_shape_fn(get(&deltas[0]).dim(), |idx| {
+            f(&mut deltas.iter().map(|x| get(x)[idx]).collect::<Vec<f64>>())
+        })
+    };
+    (layer(|x| &x.0), layer(|x| &x.1))
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|x, y| x.total_cmp(y));
+    let mid = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[mid - 1] + values[mid]) / 2.0,
+        _ => values[mid],
+    }
+}
+
+pub fn norm(delta: &Delta) -> f64 {
+    (delta
+        .0
+        .iter()
+        .chain(delta.1.iter())
+        .map(|x| x * x)
+        .sum::<f64>())
+    .sqrt()
+}
+
+fn distance(x: &Delta, y: &Delta) -> f64 {
+    norm(&(&x.0 - &y.0, &x.1 - &y.1))
+}
+
+// scales the delta down so its L2 norm is at most max
+pub fn clip(delta: Delta, max: f64) -> Delta {
+    let norm = norm(&delta);
+    match norm > max {
+        true => (delta.0 * (max / norm), delta.1 * (max / norm)),
+        false => delta,
+    }
+}
+
+// Krum picks the delta closest to its n - f - 2 nearest neighbours
+fn krum(deltas: &[Delta], byzantine: usize) -> usize {
+    let neighbours = deltas
+        .len()
+        .saturating_sub(byzantine + 2)
+        .clamp(1, deltas.len().max(2) - 1);
+    let scores = deltas.iter().enumerate().map(|(i, x)| {
+        let mut distances = deltas
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, y)| distance(x, y).powi(2))
+            .collect::<Vec<f64>>();
+        distances.sort_by(|x, y| x.total_cmp(y));
+        distances.iter().take(neighbours).sum::<f64>()
+    });
+    scores
+        .enumerate()
+        .min_by(|x, y| x.1.total_cmp(&y.1))
+        .map_or(0, |x| x.0)
+}
+
+// distance of each delta from the coordinate-wise median, relative to the median distance
+pub fn consensus_scores(updates: &[WeightUpdate]) -> Vec<f64> {
+    let deltas = updates.iter().map(update_delta).collect::<Vec<Delta>>();
+    let consensus = coordinate_wise(&deltas, median);
+    let distances = deltas
+        .iter()
+        .map(|x| distance(x, &consensus))
+        .collect::<Vec<f64>>();
+    let scale = median(&mut distances.clone());
+    distances
+        .iter()
+        .map(|x| match scale > 0.0 {
+            true => x / scale,
+            false if *x > 0.0 => f64::INFINITY,
+            false => 0.0,
+        })
+        .collect()
+}
+
+pub fn accuracy(model: &Model, images: Vec<Vec<f64>>, targets: &[u8]) -> f64 {
+    model
+        .infer2d(images)
+        .iter()
+        .zip(targets)
+        .filter(|(x, y)| x == y)
+        .count() as f64
+        / targets.len() as f64
+}
+
+// how much accuracy on held-out data falls from the base's when the update is applied to it
+pub fn accuracy_drop(
+    base: &Model,
+    base_accuracy: f64,
+    update: &WeightUpdate,
+    images: &[Vec<f64>],
+    targets: &[u8],
+) -> f64 {
+    let mut updated = base.clone();
+    apply_delta(&mut updated, update_delta(update));
+    base_accuracy - accuracy(&updated, images.to_vec(), targets)
+}
+
+pub fn apply_delta(model: &mut Model, delta: (Array2<f64>, Array2<f64>)) {
+    model.weights.0 = &model.weights.0 + &delta.0;
+    model.weights.1 = &model.weights.1 + &delta.1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use crate::util::approximate_equal;
+
+    #[test]
+    fn test_update_round_trip() {
+        let base = Model::random(20, 8, default_classes(3), (0.1, 0.1));
+        let local = Model::random(20, 8, default_classes(3), (0.1, 0.1));
+        let update = make_update(4, &base, &local, 10);
+        assert!(matches_model(&base, &update));
+        let mut res = base.clone();
+        apply_delta(&mut res, update_delta(&update));
+        assert!(res
+            .weights
+            .0
+            .iter()
+            .zip(local.weights.0.iter())
+            .all(|(x, y)| approximate_equal(*x, *y, None)));
+    }
+
+    #[test]
+    fn test_fed_avg() {
+        let update = |value: f64, samples: usize| WeightUpdate {
+            base_version: 1,
+            delta: (vec![vec![value; 2]; 2], vec![vec![value; 1]; 2]),
+            samples,
+        };
+        let delta = fed_avg(&[update(1.0, 30), update(-1.0, 10)]);
+        assert!(delta.0.iter().all(|x| approximate_equal(*x, 0.5, None)));
+        assert!(delta.1.iter().all(|x| approximate_equal(*x, 0.5, None)));
+        let delta = fed_avg(&[update(1.0, 0), update(3.0, 0)]);
+        assert!(delta.0.iter().all(|x| approximate_equal(*x, 2.0, None)));
+        let delta = fed_avg(&[update(1.0, usize::MAX), update(1.0, usize::MAX)]);
+        assert!(delta.0.iter().all(|x| approximate_equal(*x, 1.0, None)));
+    }
+
+    fn constant_update(value: f64) -> WeightUpdate {
+        WeightUpdate {
+            base_version: 1,
+            delta: (vec![vec![value; 2]; 2], vec![vec![value; 1]; 2]),
+            samples: 1,
+        }
+    }
+
+    #[test]
+    fn test_robust_aggregation() {
+        let updates = [1.0, 1.1, 0.9, 1.0, 100.0].map(constant_update);
+        let aggregate = |method: Aggregation| {
+            Aggregator {
+                method,
+                ..Default::default()
+            }
+            .aggregate(&updates)
+        };
+        let median = aggregate(Aggregation::Median);
+        assert!(median.0.iter().all(|x| approximate_equal(*x, 1.0, None)));
+        let trimmed = Aggregator {
+            method: Aggregation::TrimmedMean,
+            trim: 0.2,
+            ..Default::default()
+        }
+        .aggregate(&updates);
+        assert!(trimmed
+            .1
+            .iter()
+            .all(|x| approximate_equal(*x, 1.1 / 3.0 + 2.0 / 3.0, None)));
+        let krum = aggregate(Aggregation::Krum);
+        assert!(krum.0.iter().all(|x| approximate_equal(*x, 1.0, None)));
+        let mean = aggregate(Aggregation::FedAvg);
+        assert!(mean.0.iter().all(|x| *x > 20.0));
+    }
+
+    #[test]
+    fn test_clip() {
+        let clipped = Aggregator {
+            clip_norm: Some(2.0),
+            ..Default::default()
+        }
+        .aggregate(&[constant_update(10.0)]);
+        assert!(approximate_equal(norm(&clipped), 2.0, None));
+        let small = clip(update_delta(&constant_update(0.1)), 2.0);
+        assert!(small.0.iter().all(|x| approximate_equal(*x, 0.1, None)));
+    }
+
+    #[test]
+    fn test_consensus_scores() {
+        let scores = consensus_scores(&[1.0, 1.1, 0.9, 1.0, 100.0].map(constant_update));
+        assert!(scores[..4].iter().all(|x| *x < 1.5));
+        assert!(scores[4] > 100.0);
+        let scores = consensus_scores(&[constant_update(1.0), constant_update(1.0)]);
+        assert_eq!(scores, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_accuracy_drop() {
+        let base = Model::random(2, 2, default_classes(2), (0.1, 0.1));
+        let images = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let targets = base.infer2d(images.clone());
+        let update = make_update(1, &base, &base, 1);
+        assert_eq!(accuracy(&base, images.clone(), &targets), 1.0);
+        assert_eq!(accuracy_drop(&base, 1.0, &update, &images, &targets), 0.0);
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            Aggregation::from_name("trimmed-mean"),
+            Some(Aggregation::TrimmedMean)
+        );
+        assert_eq!(Aggregation::from_name("FedAvg"), Some(Aggregation::FedAvg));
+        assert_eq!(Aggregation::from_name("average"), None);
+    }
+
+    #[test]
+    fn test_matches_model() {
+        let model = Model::random(2, 2, default_classes(1), (0.1, 0.1));
+        let mut update = WeightUpdate {
+            base_version: 1,
+            delta: (vec![vec![0.0; 2]; 2], vec![vec![0.0; 1]; 2]),
+            samples: 1,
+        };
+        assert!(matches_model(&model, &update));
+        update.delta.0[1].push(0.0);
+        assert!(!matches_model(&model, &update));
+        update.delta.0[1].pop();
+        update.delta.1[0][0] = f64::NAN;
+        assert!(!matches_model(&model, &update));
+    }
+}
@@ -0,0 +1,144 @@
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Step {
+    pub step: usize,
+    pub epoch: usize,
+    pub loss: f64,
+    pub accuracy: f64,
+    pub learning_rate: f64,
+    // seconds since training started
+    pub elapsed: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Epoch {
+    pub epoch: usize,
+    pub steps: usize,
+    pub loss: f64,
+    pub accuracy: f64,
+    pub learning_rate: f64,
+    pub elapsed: f64,
+}
+
+// the clock is left to the caller since std::time is unavailable on wasm
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TrainingHistory {
+    pub steps: Vec<Step>,
+    pub epochs: Vec<Epoch>,
+}
+
+impl TrainingHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_step(&mut self, loss: f64, accuracy: f64, learning_rate: f64, elapsed: f64) {
+        self.steps.push(Step {
+            step: self.steps.len(),
+            epoch: self.epochs.len(),
+            loss,
+            accuracy,
+            learning_rate,
+            elapsed,
+        });
+    }
+
+    // closes the current epoch with the mean metrics of its steps
+    pub fn end_epoch(&mut self, elapsed: f64) {
+        let steps = self
+            .steps
+            .iter()
+            .filter(|x| x.epoch == self.epochs.len())
+            .collect::<Vec<&Step>>();
+        if steps.is_empty() {
+            return;
+        }
+        let mean =
+            |f: fn(&Step) -> f64| steps.iter().map(|x| f(x)).sum::<f64>() / steps.len() as f64;
+        self.epochs.push(Epoch {
+            epoch: self.epochs.len(),
+            steps: steps.len(),
+            loss: mean(|x| x.loss),
+            accuracy: mean(|x| x.accuracy),
+            learning_rate: steps[steps.len() - 1].learning_rate,
+            elapsed,
+        });
+    }
+
+    pub fn last_step(&self) -> Option<&Step> {
+        self.steps.last()
+    }
+
+    pub fn steps_csv(&self) -> String {
+        let mut res = "step,epoch,loss,accuracy,learning_rate,elapsed\n".to_string();
+        for x in self.steps.iter() {
+            res += &format!(
+                "{},{},{},{},{},{}\n",
+                x.step, x.epoch, x.loss, x.accuracy, x.learning_rate, x.elapsed
+            );
+        }
+        res
+    }
+
+    pub fn epochs_csv(&self) -> String {
+        let mut res = "epoch,steps,loss,accuracy,learning_rate,elapsed\n".to_string();
+        for x in self.epochs.iter() {
+            res += &format!(
+                "{},{},{},{},{},{}\n",
+                x.epoch, x.steps, x.loss, x.accuracy, x.learning_rate, x.elapsed
+            );
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> TrainingHistory {
+        let mut history = TrainingHistory::new();
+        history.record_step(2.0, 0.1, 0.1, 0.5);
+        history.record_step(1.0, 0.3, 0.1, 1.0);
+        history.end_epoch(1.0);
+        history.record_step(0.5, 0.8, 0.05, 1.5);
+        history
+    }
+
+    #[test]
+    fn test_epochs() {
+        let mut history = history();
+        assert_eq!(history.epochs.len(), 1);
+        assert_eq!(history.epochs[0].steps, 2);
+        assert_eq!(history.epochs[0].loss, 1.5);
+        assert_eq!(history.epochs[0].accuracy, 0.2);
+        assert_eq!(history.last_step().unwrap().epoch, 1);
+        history.end_epoch(1.5);
+        history.end_epoch(2.0);
+        assert_eq!(history.epochs.len(), 2);
+        assert_eq!(history.epochs[1].learning_rate, 0.05);
+    }
+
+    #[test]
+    fn test_csv() {
+        let history = history();
+        let steps = history.steps_csv();
+        let lines = steps.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "step,epoch,loss,accuracy,learning_rate,elapsed");
+        assert_eq!(lines[3], "2,1,0.5,0.8,0.05,1.5");
+        assert_eq!(
+            history.epochs_csv().lines().nth(1),
+            Some("0,2,1.5,0.2,0.1,1")
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let history = history();
+        let json = serde_json::to_string(&history).unwrap();
+        let res: TrainingHistory = serde_json::from_str(&json).unwrap();
+        assert_eq!(res, history);
+    }
+}
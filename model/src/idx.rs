@@ -0,0 +1,152 @@
+use crate::preprocess;
+use crate::util::{Data, DataSingle};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result};
+use std::path::Path;
+
+const IDX_UBYTE: u8 = 0x08;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+pub struct Idx {
+    pub dims: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+pub fn read_idx<R: Read>(mut reader: R) -> Result<Idx> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic[0] != 0 || magic[1] != 0 {
+        return Err(Error::new(ErrorKind::InvalidData, "not an idx file"));
+    }
+    if magic[2] != IDX_UBYTE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("unsupported idx data type 0x{:02x}", magic[2]),
+        ));
+    }
+    let mut dims = Vec::new();
+    for _ in 0..magic[3] {
+        let mut dim = [0u8; 4];
+        reader.read_exact(&mut dim)?;
+        dims.push(u32::from_be_bytes(dim) as usize);
+    }
+    let mut data = vec![0u8; dims.iter().product()];
+    reader.read_exact(&mut data)?;
+    Ok(Idx { dims, data })
+}
+
+pub fn open_idx<P: AsRef<Path>>(path: P) -> Result<Idx> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 2];
+    file.read_exact(&mut magic)?;
+    let reader = (&magic[..]).chain(file);
+    if magic == GZIP_MAGIC {
+        read_idx(GzDecoder::new(reader))
+    } else {
+        read_idx(reader)
+    }
+}
+
+pub fn idx_to_data(images: Idx, labels: Idx) -> Result<Data> {
+    if images.dims.len() < 2 || labels.dims.len() != 1 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "expected an image idx3 file and a label idx1 file",
+        ));
+    }
+    if images.dims[0] != labels.dims[0] {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} images but {} labels", images.dims[0], labels.dims[0]),
+        ));
+    }
+    let size = images.dims[1..].iter().product::<usize>();
+    if size == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("images of size {:?} have no pixels", &images.dims[1..]),
+        ));
+    }
+    Ok(Data {
+        data: images
+            .data
+            .chunks(size)
+            .zip(labels.data)
+            .map(|(image, target)| DataSingle {
+                target,
+                image: preprocess::normalize(
+                    &image.iter().map(|&x| x as f64).collect::<Vec<f64>>(),
+                    255.0,
+                ),
+            })
+            .collect(),
+    })
+}
+
+pub fn read_data<P: AsRef<Path>>(images: P, labels: P) -> Result<Data> {
+    idx_to_data(open_idx(images)?, open_idx(labels)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn images() -> Vec<u8> {
+        let mut res = vec![0, 0, IDX_UBYTE, 3, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2];
+        res.extend([0, 255, 51, 0, 255, 255, 0, 0]);
+        res
+    }
+
+    fn labels() -> Vec<u8> {
+        vec![0, 0, IDX_UBYTE, 1, 0, 0, 0, 2, 3, 7]
+    }
+
+    #[test]
+    fn test_read_idx() {
+        let idx = read_idx(&images()[..]).unwrap();
+        assert_eq!(idx.dims, vec![2, 2, 2]);
+        assert_eq!(idx.data.len(), 8);
+    }
+
+    #[test]
+    fn test_read_idx_invalid() {
+        assert!(read_idx(&[1, 2, 3, 4][..]).is_err());
+        assert!(read_idx(&[0, 0, 0x0d, 1, 0, 0, 0, 1][..]).is_err());
+        assert!(read_idx(&labels()[..9]).is_err());
+    }
+
+    #[test]
+    fn test_idx_to_data() {
+        let data = idx_to_data(
+            read_idx(&images()[..]).unwrap(),
+            read_idx(&labels()[..]).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(data.data.len(), 2);
+        assert_eq!(data.data[0].target, 3);
+        assert_eq!(data.data[0].image, vec![0.0, 1.0, 0.2, 0.0]);
+        assert_eq!(data.data[1].target, 7);
+    }
+
+    #[test]
+    fn test_idx_to_data_empty_images() {
+        let images =
+            read_idx(&[0, 0, IDX_UBYTE, 3, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 2][..]).unwrap();
+        assert!(idx_to_data(images, read_idx(&labels()[..]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_open_idx_gzip() {
+        let path = std::env::temp_dir().join("mnist_wasm_test-images-idx3-ubyte.gz");
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(&images()).unwrap();
+        encoder.finish().unwrap();
+        let idx = open_idx(&path).unwrap();
+        assert_eq!(idx.dims, vec![2, 2, 2]);
+        assert_eq!(idx.data, images()[16..].to_vec());
+    }
+}
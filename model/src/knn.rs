@@ -0,0 +1,103 @@
+use crate::classifier::{Classifier, ClassifierKind, FitOptions, SavedClassifier};
+use crate::util::Data;
+use serde_derive::{Deserialize, Serialize};
+
+// k nearest neighbours over binarised images, each packed into 64 pixel words so the
+// hamming distance is a popcount of the xor
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Knn {
+    pub k: usize,
+    // pixels above this are ink
+    pub threshold: f64,
+    pub classes: usize,
+    pub examples: Vec<Vec<u64>>,
+    pub targets: Vec<u8>,
+}
+
+impl Knn {
+    pub fn new(k: usize, threshold: f64, classes: usize) -> Self {
+        Self {
+            k,
+            threshold,
+            classes,
+            examples: Vec::new(),
+            targets: Vec::new(),
+        }
+    }
+
+    pub fn pack(&self, image: &[f64]) -> Vec<u64> {
+        image
+            .chunks(64)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, x)| **x > self.threshold)
+                    .fold(0, |word, (i, _)| word | (1 << i))
+            })
+            .collect()
+    }
+}
+
+pub fn hamming(a: &[u64], b: &[u64]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
+
+impl Classifier for Knn {
+    fn kind(&self) -> ClassifierKind {
+        ClassifierKind::Knn
+    }
+
+    // memorises the data; the options don't apply
+    fn fit(&mut self, data: &Data, _: &FitOptions) {
+        self.examples = data.data.iter().map(|x| self.pack(&x.image)).collect();
+        self.targets = data.data.iter().map(|x| x.target).collect();
+    }
+
+    // the share of the k nearest examples in each class
+    fn predict_proba(&self, images: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        let k = self.k.min(self.examples.len()).max(1);
+        images
+            .iter()
+            .map(|image| {
+                let packed = self.pack(image);
+                let mut distances = self
+                    .examples
+                    .iter()
+                    .zip(&self.targets)
+                    .map(|(x, target)| (hamming(x, &packed), *target))
+                    .collect::<Vec<(u32, u8)>>();
+                if distances.len() > k {
+                    distances.select_nth_unstable(k - 1);
+                    distances.truncate(k);
+                }
+                let mut res = vec![0.0; self.classes];
+                for (_, target) in &distances {
+                    res[*target as usize] += 1.0 / k as f64;
+                }
+                res
+            })
+            .collect()
+    }
+
+    fn serialise(&self) -> SavedClassifier {
+        SavedClassifier::Knn(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hamming() {
+        let knn = Knn::new(1, 0.5, 2);
+        let mut image = vec![0.0; 70];
+        image[0] = 1.0;
+        image[65] = 0.9;
+        let packed = knn.pack(&image);
+        assert_eq!(packed, vec![1, 2]);
+        assert_eq!(hamming(&packed, &knn.pack(&[0.0; 70])), 2);
+        assert_eq!(hamming(&packed, &packed), 0);
+    }
+}
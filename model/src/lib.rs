@@ -1,6 +1,35 @@
+#[cfg(all(
+    any(feature = "blas-netlib", feature = "blas-openblas"),
+    not(target_arch = "wasm32")
+))]
+extern crate blas_src;
+
 pub mod activations;
+pub mod adversarial;
+pub mod calibration;
+pub mod classifier;
+pub mod dataset;
+pub mod distill;
+pub mod ensemble;
+pub mod expression;
+pub mod federated;
+pub mod history;
+pub mod idx;
+pub mod knn;
+pub mod logistic;
 pub mod model;
+pub mod ood;
+pub mod optimizer;
+pub mod preprocess;
+pub mod prune;
+pub mod replay;
+pub mod saliency;
+pub mod search;
+pub mod segment;
+pub mod sparse;
 pub mod util;
+pub mod vae;
 
 pub use crate::activations::ActivationFunctions;
+pub use crate::ensemble::Ensemble;
 pub use crate::model::Model;
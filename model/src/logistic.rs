@@ -0,0 +1,119 @@
+use crate::activations::ActivationFunctions;
+use crate::classifier::{Classifier, ClassifierKind, FitOptions, SavedClassifier};
+use crate::util::{get_sample_block_with, one_hot, split_chunk, to_array2, Data};
+use ndarray::{Array1, Array2, Axis};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LogisticWeights {
+    pub weights: Vec<Vec<f64>>,
+    pub bias: Vec<f64>,
+    #[serde(default)]
+    pub learning_rate: f64,
+    #[serde(default)]
+    pub l2: f64,
+}
+
+// multinomial logistic regression: a softmax over one linear layer, fitted by minibatch sgd
+#[derive(Debug, Clone)]
+pub struct LogisticRegression {
+    pub weights: Array2<f64>,
+    pub bias: Array1<f64>,
+    pub learning_rate: f64,
+    pub l2: f64,
+}
+
+impl LogisticRegression {
+    pub fn new(input: usize, classes: usize, learning_rate: f64, l2: f64) -> Self {
+        Self {
+            weights: Array2::zeros((input, classes)),
+            bias: Array1::zeros(classes),
+            learning_rate,
+            l2,
+        }
+    }
+
+    pub fn from_weights(weights: LogisticWeights) -> Self {
+        Self {
+            weights: to_array2(weights.weights),
+            bias: Array1::from(weights.bias),
+            learning_rate: weights.learning_rate,
+            l2: weights.l2,
+        }
+    }
+
+    fn log_probabilities(&self, input: &Array2<f64>) -> Array2<f64> {
+        ActivationFunctions::logsoftmax2d(input.dot(&self.weights) + &self.bias)
+    }
+
+    // one step on the mean cross entropy of the batch, returning it
+    pub fn train2d(&mut self, input: Vec<Vec<f64>>, target: Vec<u8>) -> f64 {
+        let input = to_array2(input);
+        let batch = input.nrows() as f64;
+        let log_probabilities = self.log_probabilities(&input);
+        let targets = one_hot(&target, self.weights.ncols());
+        let loss = -(&targets * &log_probabilities).sum() / batch;
+        let gradients = (log_probabilities.mapv(f64::exp) - targets) / batch;
+        let weights = input.t().dot(&gradients) + &self.weights * self.l2;
+        self.weights = &self.weights - &(weights * self.learning_rate);
+        self.bias = &self.bias - &(gradients.sum_axis(Axis(0)) * self.learning_rate);
+        loss
+    }
+}
+
+impl Classifier for LogisticRegression {
+    fn kind(&self) -> ClassifierKind {
+        ClassifierKind::LogisticRegression
+    }
+
+    fn fit(&mut self, data: &Data, options: &FitOptions) {
+        let mut rng = options.rng();
+        for _ in 0..options.iterations {
+            let (images, targets) =
+                split_chunk(&get_sample_block_with(data, options.batch_size, &mut rng));
+            self.train2d(images, targets);
+        }
+    }
+
+    fn predict_proba(&self, images: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        self.log_probabilities(&to_array2(images))
+            .mapv(f64::exp)
+            .rows()
+            .into_iter()
+            .map(|x| x.to_vec())
+            .collect()
+    }
+
+    fn serialise(&self) -> SavedClassifier {
+        SavedClassifier::LogisticRegression(LogisticWeights {
+            weights: self
+                .weights
+                .rows()
+                .into_iter()
+                .map(|x| x.to_vec())
+                .collect(),
+            bias: self.bias.to_vec(),
+            learning_rate: self.learning_rate,
+            l2: self.l2,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::halves;
+
+    #[test]
+    fn test_train() {
+        let mut model = LogisticRegression::new(16, 2, 0.5, 0.0);
+        let (images, targets) = split_chunk(&halves(64, 1).data);
+        let first = model.train2d(images.clone(), targets.clone());
+        for _ in 0..20 {
+            model.train2d(images.clone(), targets.clone());
+        }
+        // starts from uniform predictions
+        assert!((first - 2f64.ln()).abs() < 1e-9);
+        assert!(model.train2d(images, targets) < first);
+    }
+}
@@ -1,14 +1,30 @@
 use crate::activations::ActivationFunctions;
-use ndarray::{Array1, Array2, Axis};
+use crate::dataset::default_classes;
+use crate::util::{argmax, random_dist_with, to_array2, Activations, Weights};
+use ndarray::{s, Array1, Array2, ArrayView2, Axis};
+use rand::Rng;
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+use rayon::prelude::*;
+
+// rows per unit of work when splitting a batch
+const BATCH_CHUNK: usize = 32;
+
+// lets tests take the serial path in a parallel build
+#[cfg(all(test, feature = "parallel", not(target_arch = "wasm32")))]
+thread_local! {
+    static SERIAL: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
 
 #[derive(Clone, Debug)]
 pub struct Model {
     pub weights: (Array2<f64>, Array2<f64>),
     pub learning_rates: (f64, f64),
+    pub classes: Vec<String>,
 }
 
 impl Model {
     pub fn new(weights: (Vec<Vec<f64>>, Vec<Vec<f64>>), learning_rates: (f64, f64)) -> Self {
+        let classes = default_classes(weights.1[0].len());
         Self {
             weights: (
                 Array2::from_shape_vec(
@@ -23,9 +39,74 @@ impl Model {
                 .unwrap(),
             ),
             learning_rates,
+            classes,
         }
     }
 
+    pub fn random(
+        input: usize,
+        hidden: usize,
+        classes: Vec<String>,
+        learning_rates: (f64, f64),
+    ) -> Self {
+        Self::random_with(
+            input,
+            hidden,
+            classes,
+            learning_rates,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    pub fn random_with<R: Rng>(
+        input: usize,
+        hidden: usize,
+        classes: Vec<String>,
+        learning_rates: (f64, f64),
+        rng: &mut R,
+    ) -> Self {
+        let mut model = Self::new(
+            (
+                random_dist_with(input as u32, hidden as u32, rng),
+                random_dist_with(hidden as u32, classes.len() as u32, rng),
+            ),
+            learning_rates,
+        );
+        model.classes = classes;
+        model
+    }
+
+    pub fn from_weights(weights: Weights, learning_rates: (f64, f64)) -> Self {
+        let mut model = Self::new(weights.weights, learning_rates);
+        if weights.classes.len() == model.num_classes() {
+            model.classes = weights.classes;
+        }
+        model
+    }
+
+    pub fn export(&self) -> Weights {
+        Weights {
+            weights: self.export_weights(),
+            classes: self.classes.clone(),
+            card: None,
+        }
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.weights.0.nrows()
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.weights.1.ncols()
+    }
+
+    pub fn class_name(&self, class: u8) -> String {
+        self.classes
+            .get(class as usize)
+            .cloned()
+            .unwrap_or_else(|| class.to_string())
+    }
+
     pub fn export_weights(&self) -> (Vec<Vec<f64>>, Vec<Vec<f64>>) {
         let mut res0 = Vec::new();
         let mut res1 = Vec::new();
@@ -38,7 +119,7 @@ impl Model {
         (res0, res1)
     }
 
-    fn update_weights(&mut self, gradients: (Array2<f64>, Array2<f64>)) {
+    pub(crate) fn update_weights(&mut self, gradients: (Array2<f64>, Array2<f64>)) {
         self.weights.0 = &self.weights.0 - &gradients.0 * self.learning_rates.0;
         self.weights.1 = &self.weights.1 - &gradients.1 * self.learning_rates.1;
     }
@@ -48,17 +129,7 @@ impl Model {
         let mut layer = input.dot(&self.weights.0);
         layer = ActivationFunctions::relu1d(layer);
         layer = layer.dot(&self.weights.1);
-        layer
-            .iter()
-            .enumerate()
-            .fold((0, 0.0), |(max_index, max_value), (index, value)| {
-                if value > &max_value {
-                    (index, *value)
-                } else {
-                    (max_index, max_value)
-                }
-            })
-            .0 as u8
+        argmax(layer.iter())
     }
 
     pub fn infer2d(&self, input: Vec<Vec<f64>>) -> Vec<u8> {
@@ -67,33 +138,67 @@ impl Model {
             input.into_iter().flatten().collect(),
         )
         .unwrap();
-        let mut layer = input.dot(&self.weights.0);
-        layer = ActivationFunctions::relu2d(layer);
-        layer = layer.dot(&self.weights.1);
-        layer
-            .axis_iter(Axis(0))
-            .map(|x| {
-                x.iter()
-                    .enumerate()
-                    .fold((0, 0.0), |(max_index, max_value), (index, value)| {
-                        if value > &max_value {
-                            (index, *value)
-                        } else {
-                            (max_index, max_value)
-                        }
-                    })
-                    .0 as u8
-            })
+        map_chunks(&input, |chunk, _| self.forward2d(chunk))
+            .into_iter()
+            .flatten()
             .collect()
     }
 
+    pub fn activations1d(&self, input: Vec<f64>) -> Activations {
+        self.activations2d(vec![input]).remove(0)
+    }
+
+    // every layer's output for each input, from the hidden relu to the log probabilities
+    pub fn activations2d(&self, input: Vec<Vec<f64>>) -> Vec<Activations> {
+        let input = to_array2(input);
+        map_chunks(&input, |chunk, _| {
+            let (hidden, logits) = self.forward_layers(chunk);
+            let log_probabilities = ActivationFunctions::logsoftmax2d(logits.clone());
+            (0..chunk.nrows())
+                .map(|i| Activations {
+                    hidden: hidden.row(i).to_vec(),
+                    logits: logits.row(i).to_vec(),
+                    log_probabilities: log_probabilities.row(i).to_vec(),
+                })
+                .collect::<Vec<Activations>>()
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    // the penultimate representation, the hidden relu layer
+    pub fn embed(&self, input: Vec<f64>) -> Vec<f64> {
+        self.embed2d(vec![input]).remove(0)
+    }
+
+    pub fn embed2d(&self, input: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        let input = to_array2(input);
+        map_chunks(&input, |chunk, _| {
+            let (hidden, _) = self.forward_layers(chunk);
+            hidden
+                .rows()
+                .into_iter()
+                .map(|x| x.to_vec())
+                .collect::<Vec<Vec<f64>>>()
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    fn forward2d(&self, input: ArrayView2<f64>) -> Vec<u8> {
+        let (_, layer) = self.forward_layers(input);
+        layer.axis_iter(Axis(0)).map(|x| argmax(x.iter())).collect()
+    }
+
     pub fn train1d(&mut self, input: Vec<f64>, target: u8) -> f64 {
         let input = Array1::from(input);
         let layer1 = input.dot(&self.weights.0);
         let layer1_relu = ActivationFunctions::relu1d(layer1);
         let layer2 = layer1_relu.dot(&self.weights.1);
         let output = ActivationFunctions::logsoftmax1d(layer2);
-        let mut target_vec = vec![0.0; 10];
+        let mut target_vec = vec![0.0; self.num_classes()];
         target_vec[target as usize] = 1.0;
         let target = Array1::from(target_vec);
         let loss = -(&target * &output).sum();
@@ -123,11 +228,39 @@ impl Model {
             input.into_iter().flatten().collect(),
         )
         .unwrap();
-        let layer1 = input.dot(&self.weights.0);
-        let layer1_relu = ActivationFunctions::relu2d(layer1);
-        let layer2 = layer1_relu.dot(&self.weights.1);
+        let (loss, gradients) = self.gradients2d(&input, &target);
+        self.update_weights(gradients);
+        loss
+    }
+
+    // mean loss and weight gradients over a batch
+    pub(crate) fn gradients2d(
+        &self,
+        input: &Array2<f64>,
+        target: &[u8],
+    ) -> (f64, (Array2<f64>, Array2<f64>)) {
+        let target_len = target.len();
+        // chunks are always reduced in order so threaded and serial runs match exactly
+        let (loss, gradients) = map_chunks(input, |chunk, start| {
+            self.backward2d(chunk, &target[start..start + chunk.nrows()], target_len)
+        })
+        .into_iter()
+        .reduce(|(loss_a, grad_a), (loss_b, grad_b)| {
+            (loss_a + loss_b, (grad_a.0 + grad_b.0, grad_a.1 + grad_b.1))
+        })
+        .unwrap();
+        (loss / target_len as f64, gradients)
+    }
+
+    fn backward2d(
+        &self,
+        input: ArrayView2<f64>,
+        target: &[u8],
+        target_len: usize,
+    ) -> (f64, (Array2<f64>, Array2<f64>)) {
+        let (layer1_relu, layer2) = self.forward_layers(input);
         let output = ActivationFunctions::logsoftmax2d(layer2);
-        let mut target_vec = vec![vec![0.0; 10]; target.len()];
+        let mut target_vec = vec![vec![0.0; self.num_classes()]; target.len()];
         for (i, t) in target.iter().enumerate() {
             target_vec[i][*t as usize] = 1.0;
         }
@@ -137,17 +270,51 @@ impl Model {
         )
         .unwrap();
         let loss = (-(&target * &output)).mean_axis(Axis(1)).unwrap();
-        let target_len = target.shape()[0];
         let target = -target / target_len as f64;
         let logsoftmax_gradients = ActivationFunctions::logsoftmax_backward2d(output, target);
-        let layer2_gradients = layer1_relu.t().dot(&logsoftmax_gradients);
+        (
+            loss.sum(),
+            self.backward_layers(input, layer1_relu, logsoftmax_gradients),
+        )
+    }
+
+    // returns the hidden relu activations and the output logits
+    pub(crate) fn forward_layers(&self, input: ArrayView2<f64>) -> (Array2<f64>, Array2<f64>) {
+        let layer1 = input.dot(&self.weights.0);
+        let layer1_relu = ActivationFunctions::relu2d(layer1);
+        let layer2 = layer1_relu.dot(&self.weights.1);
+        (layer1_relu, layer2)
+    }
+
+    pub(crate) fn log_probabilities(&self, input: ArrayView2<f64>) -> Array2<f64> {
+        let (_, logits) = self.forward_layers(input);
+        ActivationFunctions::logsoftmax2d(logits)
+    }
+
+    // weight gradients given the loss gradient with respect to the output logits
+    pub(crate) fn backward_layers(
+        &self,
+        input: ArrayView2<f64>,
+        layer1_relu: Array2<f64>,
+        logit_gradients: Array2<f64>,
+    ) -> (Array2<f64>, Array2<f64>) {
+        let layer2_gradients = layer1_relu.t().dot(&logit_gradients);
         let relu_gradients = ActivationFunctions::relu_backward2d(
             layer1_relu,
-            logsoftmax_gradients.dot(&self.weights.1.t()),
+            logit_gradients.dot(&self.weights.1.t()),
         );
         let layer1_gradients = input.t().dot(&relu_gradients);
-        self.update_weights((layer1_gradients, layer2_gradients));
-        loss.sum() / target_len as f64
+        (layer1_gradients, layer2_gradients)
+    }
+
+    // gradient with respect to the inputs given the gradient with respect to the output logits
+    pub(crate) fn input_gradients(
+        &self,
+        layer1_relu: Array2<f64>,
+        logit_gradients: Array2<f64>,
+    ) -> Array2<f64> {
+        ActivationFunctions::relu_backward2d(layer1_relu, logit_gradients.dot(&self.weights.1.t()))
+            .dot(&self.weights.0.t())
     }
 
     pub fn weights(&self) -> (Vec<f64>, Vec<f64>) {
@@ -158,14 +325,38 @@ impl Model {
     }
 }
 
+pub(crate) fn map_chunks<T, F>(input: &Array2<f64>, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(ArrayView2<f64>, usize) -> T + Sync,
+{
+    let starts = (0..input.nrows())
+        .step_by(BATCH_CHUNK)
+        .collect::<Vec<usize>>();
+    let run = |start: &usize| {
+        let end = (start + BATCH_CHUNK).min(input.nrows());
+        f(input.slice(s![*start..end, ..]), *start)
+    };
+    #[cfg(all(test, feature = "parallel", not(target_arch = "wasm32")))]
+    if SERIAL.with(|x| x.get()) {
+        return starts.iter().map(run).collect();
+    }
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    let res = starts.par_iter().map(run).collect();
+    #[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+    let res = starts.iter().map(run).collect();
+    res
+}
+
 #[cfg(test)]
+#[allow(clippy::get_first, clippy::clone_on_copy)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_train1d() {
         let input = crate::util::random_dist(1, 784).get(0).unwrap().clone();
-        let target = crate::util::random_int(1, 1)
+        let target = crate::util::random_int(1, 1, 10)
             .get(0)
             .unwrap()
             .get(0)
@@ -185,7 +376,7 @@ mod tests {
     #[test]
     fn test_train2d() {
         let input = crate::util::random_dist(128, 784);
-        let target = crate::util::random_int(1, 128).get(0).unwrap().clone();
+        let target = crate::util::random_int(1, 128, 10).get(0).unwrap().clone();
         let mut model = Model::new(
             (
                 crate::util::random_dist(784, 128),
@@ -241,4 +432,160 @@ mod tests {
         assert_eq!(weights.1.len(), 128);
         assert_eq!(weights.1[0].len(), 10);
     }
+
+    #[test]
+    fn test_classes() {
+        let classes = crate::dataset::Dataset::EmnistLetters.classes();
+        let mut model = Model::random(784, 32, classes, (0.1, 0.1));
+        assert_eq!(model.num_classes(), 26);
+        assert_eq!(model.input_size(), 784);
+        assert_eq!(model.class_name(25), "Z");
+
+        let input = crate::util::random_dist(4, 784);
+        let target = crate::util::random_int(1, 4, 26)[0].clone();
+        model.train2d(input.clone(), target);
+        assert!(model.infer2d(input).iter().all(|x| *x < 26));
+
+        let loaded = Model::from_weights(model.export(), (0.1, 0.1));
+        assert_eq!(loaded.classes, model.classes);
+        let unnamed = Model::new(model.export_weights(), (0.1, 0.1));
+        assert_eq!(unnamed.class_name(3), "3");
+    }
+
+    #[test]
+    fn test_activations() {
+        let model = Model::random(784, 32, default_classes(10), (0.1, 0.1));
+        let input = crate::util::random_dist(40, 784);
+        let activations = model.activations2d(input.clone());
+        assert_eq!(activations.len(), 40);
+        assert_eq!(activations[0].hidden.len(), 32);
+        assert_eq!(activations[0].logits.len(), 10);
+        assert!(activations[0].hidden.iter().all(|x| *x >= 0.0));
+        let total = activations[0]
+            .log_probabilities
+            .iter()
+            .map(|x| x.exp())
+            .sum::<f64>();
+        assert!(crate::util::approximate_equal(total, 1.0, None));
+        assert_eq!(model.activations1d(input[39].clone()), activations[39]);
+
+        let embeddings = model.embed2d(input.clone());
+        assert_eq!(embeddings[39], activations[39].hidden);
+        assert_eq!(model.embed(input[0].clone()), embeddings[0]);
+        assert_eq!(
+            model.infer2d(input),
+            activations
+                .iter()
+                .map(|x| argmax(x.logits.iter()))
+                .collect::<Vec<u8>>()
+        );
+    }
+
+    #[test]
+    fn test_train2d_chunks() {
+        let input = crate::util::random_dist(100, 784);
+        let target = crate::util::random_int(1, 100, 10)[0].clone();
+        let mut model = Model::random(784, 128, crate::dataset::default_classes(10), (0.1, 0.1));
+        let full = Array2::from_shape_vec((100, 784), input.clone().concat()).unwrap();
+        let (loss, gradients) = model.backward2d(full.view(), &target, 100);
+        let mut expected = model.clone();
+        expected.update_weights(gradients);
+
+        let chunked_loss = model.train2d(input, target);
+        assert!(crate::util::approximate_equal(
+            chunked_loss,
+            loss / 100.0,
+            None
+        ));
+        assert!(model
+            .weights
+            .0
+            .iter()
+            .zip(expected.weights.0.iter())
+            .all(|(x, y)| crate::util::approximate_equal(*x, *y, Some(1e-9))));
+        assert!(model
+            .weights
+            .1
+            .iter()
+            .zip(expected.weights.1.iter())
+            .all(|(x, y)| crate::util::approximate_equal(*x, *y, Some(1e-9))));
+    }
+
+    #[test]
+    fn test_train2d_seeded() {
+        use rand::{rngs::StdRng, SeedableRng};
+        let run = || {
+            let mut rng = StdRng::seed_from_u64(7);
+            let classes = crate::dataset::default_classes(10);
+            let mut model = Model::random_with(784, 128, classes, (0.1, 0.1), &mut rng);
+            let input = crate::util::random_dist_with(200, 784, &mut rng);
+            let target = (0..200).map(|x| (x % 10) as u8).collect::<Vec<u8>>();
+            let losses = (0..3)
+                .map(|_| model.train2d(input.clone(), target.clone()))
+                .collect::<Vec<f64>>();
+            (losses, model.export_weights())
+        };
+        let res = run();
+        assert_eq!(res, run());
+        #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+        {
+            SERIAL.with(|x| x.set(true));
+            let serial = run();
+            SERIAL.with(|x| x.set(false));
+            assert_eq!(res, serial);
+        }
+    }
+
+    // naive pure-Rust forward pass, so the test holds whichever matmul backend `dot` uses
+    fn reference_logits(model: &Model, input: &[f64]) -> Vec<f64> {
+        let matmul = |x: &[f64], w: &Array2<f64>| {
+            (0..w.ncols())
+                .map(|j| (0..w.nrows()).map(|i| x[i] * w[[i, j]]).sum::<f64>())
+                .collect::<Vec<f64>>()
+        };
+        let hidden = matmul(input, &model.weights.0)
+            .into_iter()
+            .map(|x| x.max(0.0))
+            .collect::<Vec<f64>>();
+        matmul(&hidden, &model.weights.1)
+    }
+
+    #[test]
+    fn test_backend_parity() {
+        let input = crate::util::random_dist(64, 784);
+        let model = Model::random(784, 128, crate::dataset::default_classes(10), (0.1, 0.1));
+        let expected = input
+            .iter()
+            .map(|x| {
+                reference_logits(&model, x)
+                    .iter()
+                    .enumerate()
+                    .fold(
+                        (0, f64::NEG_INFINITY),
+                        |(max_index, max_value), (index, value)| {
+                            if value > &max_value {
+                                (index, *value)
+                            } else {
+                                (max_index, max_value)
+                            }
+                        },
+                    )
+                    .0 as u8
+            })
+            .collect::<Vec<u8>>();
+        assert_eq!(model.infer2d(input.clone()), expected);
+        assert_eq!(model.infer1d(input[0].clone()), expected[0]);
+
+        let logits = Array2::from_shape_vec((64, 784), input.concat())
+            .unwrap()
+            .dot(&model.weights.0)
+            .mapv(|x| x.max(0.0))
+            .dot(&model.weights.1);
+        for (row, x) in logits.axis_iter(Axis(0)).zip(input.iter()) {
+            assert!(row
+                .iter()
+                .zip(reference_logits(&model, x))
+                .all(|(a, b)| crate::util::approximate_equal(*a, b, Some(1e-6))));
+        }
+    }
 }
@@ -0,0 +1,282 @@
+use crate::activations::ActivationFunctions;
+use crate::model::Model;
+use crate::util::to_array2;
+use ndarray::{Array1, Array2, Axis};
+use serde_derive::{Deserialize, Serialize};
+
+// pixels below this count as an empty canvas
+const BLANK: f64 = 1e-6;
+
+// every score is higher the less an image looks like the training data
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OodMethod {
+    // one minus the top softmax probability
+    MaxSoftmax,
+    // the negative log-sum-exp of the logits
+    #[default]
+    Energy,
+    // distance of the hidden layer to the closest class mean
+    Mahalanobis,
+}
+
+impl OodMethod {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace(['_', '-'], "").as_str() {
+            "maxsoftmax" | "msp" => Some(Self::MaxSoftmax),
+            "energy" => Some(Self::Energy),
+            "mahalanobis" => Some(Self::Mahalanobis),
+            _ => None,
+        }
+    }
+}
+
+// class conditional gaussians over the embeddings sharing one covariance
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Gaussian {
+    pub means: Vec<Vec<f64>>,
+    pub precision: Vec<Vec<f64>>,
+}
+
+impl Gaussian {
+    pub fn fit(embeddings: &[Vec<f64>], targets: &[u8], classes: usize) -> Self {
+        let embeddings = to_array2(embeddings.to_vec());
+        let size = embeddings.ncols();
+        let mut means = Array2::<f64>::zeros((classes, size));
+        let mut counts = vec![0.0; classes];
+        for (row, target) in embeddings.rows().into_iter().zip(targets) {
+            let mut mean = means.row_mut(*target as usize);
+            mean += &row;
+            counts[*target as usize] += 1.0;
+        }
+        for (mut mean, count) in means.rows_mut().into_iter().zip(&counts) {
+            mean /= f64::max(*count, 1.0);
+        }
+        let mut centered = embeddings.clone();
+        for (mut row, target) in centered.rows_mut().into_iter().zip(targets) {
+            row -= &means.row(*target as usize);
+        }
+        let mut covariance = centered.t().dot(&centered) / embeddings.nrows().max(1) as f64;
+        // dead relu units leave the covariance singular
+        let ridge = covariance.diag().mean().unwrap_or(0.0) * 1e-3 + 1e-9;
+        covariance.diag_mut().mapv_inplace(|x| x + ridge);
+        Self {
+            means: means.rows().into_iter().map(|x| x.to_vec()).collect(),
+            precision: invert(covariance)
+                .rows()
+                .into_iter()
+                .map(|x| x.to_vec())
+                .collect(),
+        }
+    }
+
+    // squared distance to the closest class mean
+    pub fn distance(&self, embedding: &[f64]) -> f64 {
+        let precision = to_array2(self.precision.clone());
+        self.means
+            .iter()
+            .map(|mean| {
+                let diff = Array1::from(embedding.to_vec()) - Array1::from(mean.clone());
+                diff.dot(&precision.dot(&diff))
+            })
+            .fold(f64::MAX, f64::min)
+    }
+}
+
+// gauss-jordan elimination with partial pivoting
+fn invert(mut matrix: Array2<f64>) -> Array2<f64> {
+    let n = matrix.nrows();
+    let mut res = Array2::<f64>::eye(n);
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|a, b| matrix[[*a, col]].abs().total_cmp(&matrix[[*b, col]].abs()))
+            .unwrap();
+        for m in [&mut matrix, &mut res] {
+            for k in 0..n {
+                m.swap([col, k], [pivot, k]);
+            }
+        }
+        let scale = matrix[[col, col]];
+        matrix.row_mut(col).mapv_inplace(|x| x / scale);
+        res.row_mut(col).mapv_inplace(|x| x / scale);
+        for row in 0..n {
+            let factor = matrix[[row, col]];
+            if row == col || factor == 0.0 {
+                continue;
+            }
+            let (pivot_row, pivot_res) = (matrix.row(col).to_owned(), res.row(col).to_owned());
+            matrix.row_mut(row).scaled_add(-factor, &pivot_row);
+            res.row_mut(row).scaled_add(-factor, &pivot_res);
+        }
+    }
+    res
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OodDetector {
+    pub method: OodMethod,
+    // images scoring above this are unrecognised
+    pub threshold: f64,
+    // softmax temperature the max-softmax score is taken at
+    #[serde(default = "OodDetector::default_temperature")]
+    pub temperature: f64,
+    #[serde(default)]
+    pub gaussian: Option<Gaussian>,
+}
+
+impl OodDetector {
+    fn default_temperature() -> f64 {
+        1.0
+    }
+
+    // sets the threshold so that the accept fraction of the given in-distribution images pass
+    pub fn fit(
+        model: &Model,
+        images: Vec<Vec<f64>>,
+        targets: &[u8],
+        method: OodMethod,
+        temperature: f64,
+        accept: f64,
+    ) -> Self {
+        let gaussian = match method {
+            OodMethod::Mahalanobis => Some(Gaussian::fit(
+                &model.embed2d(images.clone()),
+                targets,
+                model.num_classes(),
+            )),
+            _ => None,
+        };
+        let mut res = Self {
+            method,
+            threshold: f64::MAX,
+            temperature,
+            gaussian,
+        };
+        let mut scores = res.scores(model, images);
+        scores.sort_by(f64::total_cmp);
+        if !scores.is_empty() {
+            let idx = ((scores.len() as f64 * accept).ceil() as usize).clamp(1, scores.len());
+            res.threshold = scores[idx - 1];
+        }
+        res
+    }
+
+    pub fn scores(&self, model: &Model, images: Vec<Vec<f64>>) -> Vec<f64> {
+        match (self.method, &self.gaussian) {
+            (OodMethod::Mahalanobis, Some(gaussian)) => model
+                .embed2d(images)
+                .iter()
+                .map(|x| gaussian.distance(x))
+                .collect(),
+            (OodMethod::Mahalanobis, None) => vec![f64::MAX; images.len()],
+            (method, _) => {
+                let logits = model.forward_layers(to_array2(images).view()).1;
+                match method {
+                    OodMethod::MaxSoftmax => {
+                        ActivationFunctions::logsoftmax2d(logits / self.temperature)
+                            .map_axis(Axis(1), |x| 1.0 - x.fold(f64::MIN, |a, b| a.max(*b)).exp())
+                            .to_vec()
+                    }
+                    _ => logits
+                        .map_axis(Axis(1), |x| {
+                            let max = x.fold(f64::MIN, |a, b| a.max(*b));
+                            -(max + x.mapv(|v| (v - max).exp()).sum().ln())
+                        })
+                        .to_vec(),
+                }
+            }
+        }
+    }
+
+    // whether the gaussian, if any, was fitted to a model of this shape
+    pub fn fits(&self, hidden: usize, classes: usize) -> bool {
+        self.gaussian.as_ref().is_none_or(|gaussian| {
+            gaussian.means.len() == classes
+                && gaussian.means.iter().all(|x| x.len() == hidden)
+                && gaussian.precision.len() == hidden
+                && gaussian.precision.iter().all(|x| x.len() == hidden)
+        })
+    }
+
+    // empty canvases are never recognised, whatever they score
+    pub fn recognised(&self, model: &Model, images: Vec<Vec<f64>>) -> Vec<bool> {
+        let blank = images
+            .iter()
+            .map(|x| x.iter().all(|p| p.abs() < BLANK))
+            .collect::<Vec<bool>>();
+        self.scores(model, images)
+            .into_iter()
+            .zip(blank)
+            .map(|(score, blank)| !blank && score <= self.threshold)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classifier::halves;
+    use crate::dataset::default_classes;
+    use crate::util::{approximate_equal, split_chunk, train_handler};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn trained(rng: &mut StdRng) -> Model {
+        let mut model = Model::random_with(16, 8, default_classes(2), (0.1, 0.1), rng);
+        for seed in 0..100 {
+            train_handler(&halves(32, seed).data, &mut model, 32);
+        }
+        model
+    }
+
+    #[test]
+    fn test_invert() {
+        let matrix = to_array2(vec![
+            vec![4.0, 7.0, 2.0],
+            vec![3.0, 6.0, 1.0],
+            vec![2.0, 5.0, 3.0],
+        ]);
+        let product = matrix.dot(&invert(matrix.clone()));
+        for ((i, j), x) in product.indexed_iter() {
+            assert!(approximate_equal(*x, (i == j) as u8 as f64, Some(1e-9)));
+        }
+    }
+
+    #[test]
+    fn test_detectors() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let model = trained(&mut rng);
+        let (images, targets) = split_chunk(&halves(200, 100).data);
+        let (held_out, _) = split_chunk(&halves(200, 101).data);
+        // every pixel lit, unlike either class
+        let scribble = vec![vec![1.0; 16]];
+        for method in [
+            OodMethod::MaxSoftmax,
+            OodMethod::Energy,
+            OodMethod::Mahalanobis,
+        ] {
+            let detector = OodDetector::fit(&model, images.clone(), &targets, method, 1.0, 0.95);
+            let accepted = detector
+                .recognised(&model, held_out.clone())
+                .into_iter()
+                .filter(|x| *x)
+                .count();
+            assert!(accepted > 170, "{:?} accepted {}", method, accepted);
+            assert_eq!(
+                detector.recognised(&model, vec![vec![0.0; 16]]),
+                vec![false]
+            );
+            if method == OodMethod::Mahalanobis {
+                assert_eq!(detector.recognised(&model, scribble.clone()), vec![false]);
+            }
+            assert!(detector.fits(model.weights.0.ncols(), model.num_classes()));
+            assert_eq!(
+                detector.fits(model.weights.0.ncols() - 1, model.num_classes()),
+                method != OodMethod::Mahalanobis
+            );
+        }
+        assert_eq!(
+            OodMethod::from_name("max_softmax"),
+            Some(OodMethod::MaxSoftmax)
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::model::Model;
+use crate::util::{split_chunk, to_array2, DataSingle};
+use ndarray::Array2;
+use serde_derive::{Deserialize, Serialize};
+
+const MOMENTUM: f64 = 0.9;
+const BETA1: f64 = 0.9;
+const BETA2: f64 = 0.999;
+const EPSILON: f64 = 1e-8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Optimizer {
+    #[default]
+    Sgd,
+    Momentum,
+    Adam,
+}
+
+impl Optimizer {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "sgd" => Some(Self::Sgd),
+            "momentum" => Some(Self::Momentum),
+            "adam" => Some(Self::Adam),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sgd => "sgd",
+            Self::Momentum => "momentum",
+            Self::Adam => "adam",
+        }
+    }
+}
+
+// steps a model with the optimizer's running state, using the model's own learning rates
+#[derive(Debug, Clone, Default)]
+pub struct Trainer {
+    pub optimizer: Optimizer,
+    // l2 penalty added to the gradients
+    pub weight_decay: f64,
+    first: Option<Vec<Array2<f64>>>,
+    second: Option<Vec<Array2<f64>>>,
+    steps: i32,
+}
+
+impl Trainer {
+    pub fn new(optimizer: Optimizer, weight_decay: f64) -> Self {
+        Self {
+            optimizer,
+            weight_decay,
+            ..Default::default()
+        }
+    }
+
+    pub fn train2d(&mut self, model: &mut Model, input: Vec<Vec<f64>>, target: Vec<u8>) -> f64 {
+        let (loss, gradients) = model.gradients2d(&to_array2(input), &target);
+        let gradients = (
+            gradients.0 + &model.weights.0 * self.weight_decay,
+            gradients.1 + &model.weights.1 * self.weight_decay,
+        );
+        let mut step = self.step(vec![gradients.0, gradients.1]).into_iter();
+        model.update_weights((step.next().unwrap(), step.next().unwrap()));
+        loss
+    }
+
+    pub fn train_handler(
+        &mut self,
+        chunk: &[DataSingle],
+        model: &mut Model,
+        batch_size: usize,
+    ) -> (f64, f64) {
+        let (images, targets) = split_chunk(chunk);
+        let accuracy = model
+            .infer2d(images.clone())
+            .into_iter()
+            .zip(targets.iter())
+            .filter(|(x, y)| x == *y)
+            .count() as f64
+            / batch_size as f64;
+        let loss = self.train2d(model, images, targets);
+        (loss, accuracy)
+    }
+
+    // the direction to move each parameter in, before scaling by the learning rate
+    pub fn step(&mut self, gradients: Vec<Array2<f64>>) -> Vec<Array2<f64>> {
+        self.steps += 1;
+        match self.optimizer {
+            Optimizer::Sgd => gradients,
+            Optimizer::Momentum => {
+                let velocity = match self.first.take() {
+                    Some(v) => v
+                        .into_iter()
+                        .zip(gradients)
+                        .map(|(v, g)| v * MOMENTUM + g)
+                        .collect(),
+                    None => gradients,
+                };
+                self.first = Some(velocity.clone());
+                velocity
+            }
+            Optimizer::Adam => {
+                let zeros = || gradients.iter().map(|x| Array2::zeros(x.dim())).collect();
+                let first = self.first.take().unwrap_or_else(zeros);
+                let second = self.second.take().unwrap_or_else(zeros);
+                let m = first
+                    .into_iter()
+                    .zip(&gradients)
+                    .map(|(m, g)| m * BETA1 + g * (1.0 - BETA1))
+                    .collect::<Vec<Array2<f64>>>();
+                let v = second
+                    .into_iter()
+                    .zip(&gradients)
+                    .map(|(v, g)| v * BETA2 + g.mapv(|x| x * x) * (1.0 - BETA2))
+                    .collect::<Vec<Array2<f64>>>();
+                let m_scale = 1.0 / (1.0 - BETA1.powi(self.steps));
+                let v_scale = 1.0 / (1.0 - BETA2.powi(self.steps));
+                let res = m
+                    .iter()
+                    .zip(&v)
+                    .map(|(m, v)| {
+                        let mut res = m * m_scale;
+                        res.zip_mut_with(v, |x, y| *x /= (y * v_scale).sqrt() + EPSILON);
+                        res
+                    })
+                    .collect();
+                self.first = Some(m);
+                self.second = Some(v);
+                res
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn data() -> (Vec<Vec<f64>>, Vec<u8>) {
+        let images = (0..6)
+            .map(|i| (0..8).map(|j| ((i * 3 + j) % 5) as f64 / 5.0).collect())
+            .collect();
+        (images, vec![0, 1, 2, 0, 1, 2])
+    }
+
+    #[test]
+    fn test_optimizers() {
+        for optimizer in [Optimizer::Sgd, Optimizer::Momentum, Optimizer::Adam] {
+            let mut rng = StdRng::seed_from_u64(1);
+            let mut model = Model::random_with(8, 6, default_classes(3), (0.05, 0.05), &mut rng);
+            let mut trainer = Trainer::new(optimizer, 0.0);
+            let (images, targets) = data();
+            let first = trainer.train2d(&mut model, images.clone(), targets.clone());
+            let mut last = first;
+            for _ in 0..50 {
+                last = trainer.train2d(&mut model, images.clone(), targets.clone());
+            }
+            assert!(last < first, "{:?} did not reduce the loss", optimizer);
+        }
+        assert_eq!(Optimizer::from_name("Adam"), Some(Optimizer::Adam));
+        assert_eq!(Optimizer::from_name("lbfgs"), None);
+    }
+
+    #[test]
+    fn test_weight_decay() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let model = Model::random_with(8, 6, default_classes(3), (0.05, 0.05), &mut rng);
+        let (images, targets) = data();
+        let mut plain = model.clone();
+        let mut decayed = model.clone();
+        let mut trainer = Trainer::new(Optimizer::Sgd, 0.0);
+        let mut decay = Trainer::new(Optimizer::Sgd, 1.0);
+        for _ in 0..20 {
+            trainer.train2d(&mut plain, images.clone(), targets.clone());
+            decay.train2d(&mut decayed, images.clone(), targets.clone());
+        }
+        let norm = |m: &Model| m.weights.0.mapv(|x| x * x).sum();
+        assert!(norm(&decayed) < norm(&plain));
+    }
+}
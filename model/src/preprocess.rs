@@ -0,0 +1,304 @@
+pub fn binarize(image: &[f64], threshold: f64) -> Vec<f64> {
+    image
+        .iter()
+        .map(|&x| if x > threshold { 1.0 } else { 0.0 })
+        .collect()
+}
+
+pub fn normalize(image: &[f64], max: f64) -> Vec<f64> {
+    image.iter().map(|&x| (x / max).clamp(0.0, 1.0)).collect()
+}
+
+pub fn transpose(image: &[f64]) -> Vec<f64> {
+    let side = (image.len() as f64).sqrt() as usize;
+    (0..image.len())
+        .map(|i| image[(i % side) * side + i / side])
+        .collect()
+}
+
+// MNIST digits fit a 20x20 box inside the 28x28 frame
+pub const MNIST_BOX_RATIO: f64 = 20.0 / 28.0;
+
+pub fn bounding_box(image: &[f64], side: usize) -> Option<(usize, usize, usize, usize)> {
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (i, x) in image.iter().enumerate() {
+        if *x <= 0.0 {
+            continue;
+        }
+        let (row, col) = (i / side, i % side);
+        bounds = Some(match bounds {
+            Some((top, bottom, left, right)) => (
+                top.min(row),
+                bottom.max(row + 1),
+                left.min(col),
+                right.max(col + 1),
+            ),
+            None => (row, row + 1, col, col + 1),
+        });
+    }
+    bounds
+}
+
+pub fn crop(image: &[f64], side: usize, bounds: (usize, usize, usize, usize)) -> Vec<f64> {
+    let (top, bottom, left, right) = bounds;
+    (top..bottom)
+        .flat_map(|row| image[row * side + left..row * side + right].to_vec())
+        .collect()
+}
+
+pub fn resize(image: &[f64], shape: (usize, usize), new_shape: (usize, usize)) -> Vec<f64> {
+    let (h, w) = shape;
+    let (nh, nw) = new_shape;
+    let sample = |pos: usize, old: usize, new: usize| {
+        let x = ((pos as f64 + 0.5) * old as f64 / new as f64 - 0.5).clamp(0.0, (old - 1) as f64);
+        let low = x.floor() as usize;
+        (low, (low + 1).min(old - 1), x - low as f64)
+    };
+    let mut res = Vec::with_capacity(nh * nw);
+    for row in 0..nh {
+        let (r0, r1, dr) = sample(row, h, nh);
+        for col in 0..nw {
+            let (c0, c1, dc) = sample(col, w, nw);
+            let top = image[r0 * w + c0] * (1.0 - dc) + image[r0 * w + c1] * dc;
+            let bottom = image[r1 * w + c0] * (1.0 - dc) + image[r1 * w + c1] * dc;
+            res.push(top * (1.0 - dr) + bottom * dr);
+        }
+    }
+    res
+}
+
+pub fn fit(image: &[f64], shape: (usize, usize), size: usize) -> (Vec<f64>, (usize, usize)) {
+    let (h, w) = shape;
+    let scale = size as f64 / h.max(w) as f64;
+    let new_shape = (
+        ((h as f64 * scale).round() as usize).max(1),
+        ((w as f64 * scale).round() as usize).max(1),
+    );
+    (resize(image, shape, new_shape), new_shape)
+}
+
+pub fn center_of_mass(image: &[f64], shape: (usize, usize)) -> Option<(f64, f64)> {
+    let (total, row, col) =
+        image
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0, 0.0), |(total, row, col), (i, x)| {
+                (
+                    total + x,
+                    row + x * (i / shape.1) as f64,
+                    col + x * (i % shape.1) as f64,
+                )
+            });
+    if total > 0.0 {
+        Some((row / total, col / total))
+    } else {
+        None
+    }
+}
+
+fn center_shift(image: &[f64], shape: (usize, usize), side: usize) -> Option<(i64, i64)> {
+    let (com_row, com_col) = center_of_mass(image, shape)?;
+    Some((
+        (side as f64 / 2.0 - com_row).round() as i64,
+        (side as f64 / 2.0 - com_col).round() as i64,
+    ))
+}
+
+pub fn center(image: &[f64], shape: (usize, usize), side: usize) -> Vec<f64> {
+    let mut res = vec![0.0; side * side];
+    let (shift_row, shift_col) = match center_shift(image, shape, side) {
+        Some(shift) => shift,
+        None => return res,
+    };
+    for (i, x) in image.iter().enumerate() {
+        let row = (i / shape.1) as i64 + shift_row;
+        let col = (i % shape.1) as i64 + shift_col;
+        if (0..side as i64).contains(&row) && (0..side as i64).contains(&col) {
+            res[row as usize * side + col as usize] = *x;
+        }
+    }
+    res
+}
+
+pub fn gaussian_blur(image: &[f64], side: usize, sigma: f64) -> Vec<f64> {
+    let radius = (3.0 * sigma).ceil() as i64;
+    let kernel = (-radius..=radius)
+        .map(|x| (-(x * x) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect::<Vec<f64>>();
+    let sum = kernel.iter().sum::<f64>();
+    let kernel = kernel.iter().map(|x| x / sum).collect::<Vec<f64>>();
+    let pass = |image: &[f64], horizontal: bool| {
+        let mut res = vec![0.0; side * side];
+        for row in 0..side as i64 {
+            for col in 0..side as i64 {
+                res[(row * side as i64 + col) as usize] = kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, weight)| {
+                        let offset = k as i64 - radius;
+                        let (r, c) = if horizontal {
+                            (row, col + offset)
+                        } else {
+                            (row + offset, col)
+                        };
+                        if (0..side as i64).contains(&r) && (0..side as i64).contains(&c) {
+                            weight * image[(r * side as i64 + c) as usize]
+                        } else {
+                            0.0
+                        }
+                    })
+                    .sum();
+            }
+        }
+        res
+    };
+    pass(&pass(image, true), false)
+}
+
+pub fn mnist_style(image: &[f64], side: usize, blur: Option<f64>) -> Vec<f64> {
+    let bounds = match bounding_box(image, side) {
+        Some(bounds) => bounds,
+        None => return image.to_vec(),
+    };
+    let cropped = crop(image, side, bounds);
+    let shape = (bounds.1 - bounds.0, bounds.3 - bounds.2);
+    let box_size = (side as f64 * MNIST_BOX_RATIO).round() as usize;
+    let (resized, shape) = fit(&cropped, shape, box_size);
+    let mut res = center(&resized, shape, side);
+    if let Some(sigma) = blur {
+        res = gaussian_blur(&res, side, sigma);
+    }
+    res.iter().map(|x| x.clamp(0.0, 1.0)).collect()
+}
+
+// carries values laid out like mnist_style(image) back onto the pixels of image
+pub fn unstyle(values: &[f64], image: &[f64], side: usize) -> Vec<f64> {
+    let bounds = match bounding_box(image, side) {
+        Some(bounds) => bounds,
+        None => return values.to_vec(),
+    };
+    let shape = (bounds.1 - bounds.0, bounds.3 - bounds.2);
+    let box_size = (side as f64 * MNIST_BOX_RATIO).round() as usize;
+    let (resized, new_shape) = fit(&crop(image, side, bounds), shape, box_size);
+    let (shift_row, shift_col) = match center_shift(&resized, new_shape, side) {
+        Some(shift) => shift,
+        None => return vec![0.0; side * side],
+    };
+    let project = |pos: usize, start: usize, old: usize, new: usize, shift: i64| {
+        let scaled = (pos as f64 - start as f64 + 0.5) * new as f64 / old as f64 - 0.5;
+        scaled.round() as i64 + shift
+    };
+    (0..side * side)
+        .map(|i| {
+            let row = project(i / side, bounds.0, shape.0, new_shape.0, shift_row);
+            let col = project(i % side, bounds.2, shape.1, new_shape.1, shift_col);
+            match (0..side as i64).contains(&row) && (0..side as i64).contains(&col) {
+                true => values[row as usize * side + col as usize],
+                false => 0.0,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::approximate_equal;
+
+    #[test]
+    fn test_binarize() {
+        let image = vec![0.0, 0.2, 0.5, 0.9];
+        assert_eq!(binarize(&image, 0.0), vec![0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(binarize(&image, 0.5), vec![0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let image = vec![0.0, 51.0, 255.0, 300.0];
+        let res = normalize(&image, 255.0);
+        assert_eq!(res, vec![0.0, 0.2, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_transpose() {
+        let image = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0];
+        let res = transpose(&image);
+        assert_eq!(res, vec![1.0, 4.0, 7.0, 2.0, 5.0, 8.0, 3.0, 6.0, 9.0]);
+        assert_eq!(transpose(&res), image);
+    }
+
+    fn corner_digit() -> Vec<f64> {
+        let mut image = vec![0.0; 28 * 28];
+        for row in 1..9 {
+            for col in 2..6 {
+                image[row * 28 + col] = 1.0;
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        assert_eq!(bounding_box(&corner_digit(), 28), Some((1, 9, 2, 6)));
+        assert_eq!(bounding_box(&[0.0; 28 * 28], 28), None);
+        let cropped = crop(&corner_digit(), 28, (1, 9, 2, 6));
+        assert_eq!(cropped, vec![1.0; 32]);
+    }
+
+    #[test]
+    fn test_resize() {
+        let res = resize(&[0.5; 12], (3, 4), (6, 8));
+        assert_eq!(res.len(), 48);
+        assert!(res.iter().all(|x| approximate_equal(*x, 0.5, None)));
+        let (res, shape) = fit(&[1.0; 32], (8, 4), 20);
+        assert_eq!(shape, (20, 10));
+        assert_eq!(res.len(), 200);
+    }
+
+    #[test]
+    fn test_center_of_mass() {
+        let com = center_of_mass(&corner_digit(), (28, 28)).unwrap();
+        assert!(approximate_equal(com.0, 4.5, None));
+        assert!(approximate_equal(com.1, 3.5, None));
+        assert_eq!(center_of_mass(&[0.0; 4], (2, 2)), None);
+    }
+
+    #[test]
+    fn test_gaussian_blur() {
+        let mut image = vec![0.0; 9 * 9];
+        image[4 * 9 + 4] = 1.0;
+        let res = gaussian_blur(&image, 9, 1.0);
+        assert!(approximate_equal(res.iter().sum::<f64>(), 1.0, Some(1e-2)));
+        assert!(res[4 * 9 + 4] < 1.0);
+        assert!(approximate_equal(res[4 * 9 + 3], res[3 * 9 + 4], None));
+    }
+
+    #[test]
+    fn test_mnist_style() {
+        let res = mnist_style(&corner_digit(), 28, None);
+        assert_eq!(res.len(), 28 * 28);
+        let (top, bottom, left, right) = bounding_box(&res, 28).unwrap();
+        assert_eq!(bottom - top, 20);
+        assert_eq!(right - left, 10);
+        let com = center_of_mass(&res, (28, 28)).unwrap();
+        assert!(approximate_equal(com.0, 14.0, Some(1.0)));
+        assert!(approximate_equal(com.1, 14.0, Some(1.0)));
+        assert_eq!(
+            mnist_style(&[0.0; 28 * 28], 28, Some(1.0)),
+            vec![0.0; 28 * 28]
+        );
+    }
+
+    #[test]
+    fn test_unstyle() {
+        let image = corner_digit();
+        let res = unstyle(&mnist_style(&image, 28, None), &image, 28);
+        assert_eq!(res.len(), 28 * 28);
+        assert!(image
+            .iter()
+            .zip(res.iter())
+            .all(|(x, y)| (*x > 0.0) == (*y > 0.0)));
+        let values = vec![1.0; 28 * 28];
+        assert_eq!(unstyle(&values, &[0.0; 28 * 28], 28), values);
+    }
+}
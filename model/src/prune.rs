@@ -0,0 +1,129 @@
+use crate::model::Model;
+use crate::util::{train_handler, DataSingle};
+use ndarray::{Array2, Axis};
+
+// 1.0 where a weight is kept, 0.0 where it has been pruned
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mask {
+    pub masks: (Array2<f64>, Array2<f64>),
+}
+
+impl Mask {
+    pub fn from_model(model: &Model) -> Self {
+        let keep = |x: &f64| if *x != 0.0 { 1.0 } else { 0.0 };
+        Self {
+            masks: (model.weights.0.map(keep), model.weights.1.map(keep)),
+        }
+    }
+
+    pub fn apply(&self, model: &mut Model) {
+        model.weights.0 = &model.weights.0 * &self.masks.0;
+        model.weights.1 = &model.weights.1 * &self.masks.1;
+    }
+
+    pub fn sparsity(&self) -> f64 {
+        let pruned = self.masks.0.iter().chain(self.masks.1.iter());
+        let total = self.masks.0.len() + self.masks.1.len();
+        pruned.filter(|x| **x == 0.0).count() as f64 / total as f64
+    }
+
+    // one training step that keeps pruned weights at zero, for fine-tuning
+    pub fn train_handler(
+        &self,
+        chunk: &[DataSingle],
+        model: &mut Model,
+        batch_size: usize,
+    ) -> (f64, f64) {
+        let res = train_handler(chunk, model, batch_size);
+        self.apply(model);
+        res
+    }
+}
+
+fn prune_layer(layer: &mut Array2<f64>, sparsity: f64) {
+    let count = (layer.len() as f64 * sparsity.clamp(0.0, 1.0)).floor() as usize;
+    let mut order = (0..layer.len()).collect::<Vec<usize>>();
+    let values = layer.iter().map(|x| x.abs()).collect::<Vec<f64>>();
+    order.sort_by(|a, b| values[*a].total_cmp(&values[*b]));
+    let cols = layer.ncols();
+    for i in order.into_iter().take(count) {
+        layer[[i / cols, i % cols]] = 0.0;
+    }
+}
+
+// zeros the smallest `sparsity` fraction of weights in each layer
+pub fn magnitude_prune(model: &mut Model, sparsity: f64) -> Mask {
+    prune_layer(&mut model.weights.0, sparsity);
+    prune_layer(&mut model.weights.1, sparsity);
+    Mask::from_model(model)
+}
+
+// keeps the `hidden` units with the largest incoming times outgoing weight norm
+pub fn structured_prune(model: &Model, hidden: usize) -> Model {
+    let incoming = model.weights.0.map_axis(Axis(0), |x| x.dot(&x).sqrt());
+    let outgoing = model.weights.1.map_axis(Axis(1), |x| x.dot(&x).sqrt());
+    let scores = incoming * outgoing;
+    let mut order = (0..scores.len()).collect::<Vec<usize>>();
+    order.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]));
+    let mut keep = order.into_iter().take(hidden).collect::<Vec<usize>>();
+    keep.sort();
+    Model {
+        weights: (
+            model.weights.0.select(Axis(1), &keep),
+            model.weights.1.select(Axis(0), &keep),
+        ),
+        learning_rates: model.learning_rates,
+        classes: model.classes.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use crate::util::{random_dist, random_int};
+
+    #[test]
+    fn test_magnitude_prune() {
+        let mut model = Model::random(784, 128, default_classes(10), (0.1, 0.1));
+        let smallest = model.weights.0.iter().fold(f64::MAX, |a, b| a.min(b.abs()));
+        let mask = magnitude_prune(&mut model, 0.9);
+        assert!(crate::util::approximate_equal(
+            mask.sparsity(),
+            0.9,
+            Some(1e-3)
+        ));
+        assert!(model
+            .weights
+            .0
+            .iter()
+            .all(|x| *x == 0.0 || x.abs() > smallest));
+        assert_eq!(Mask::from_model(&model), mask);
+    }
+
+    #[test]
+    fn test_structured_prune() {
+        let mut model = Model::random(784, 128, default_classes(10), (0.1, 0.1));
+        for unit in 0..64 {
+            model.weights.0.column_mut(unit * 2).fill(0.0);
+        }
+        let pruned = structured_prune(&model, 64);
+        assert_eq!(pruned.weights.0.dim(), (784, 64));
+        assert_eq!(pruned.weights.1.dim(), (64, 10));
+        let input = random_dist(16, 784);
+        assert_eq!(pruned.infer2d(input.clone()), model.infer2d(input));
+    }
+
+    #[test]
+    fn test_fine_tune_keeps_mask() {
+        let mut model = Model::random(784, 32, default_classes(10), (0.1, 0.1));
+        let mask = magnitude_prune(&mut model, 0.5);
+        let chunk = random_dist(8, 784)
+            .into_iter()
+            .zip(random_int(1, 8, 10)[0].clone())
+            .map(|(image, target)| DataSingle { image, target })
+            .collect::<Vec<DataSingle>>();
+        mask.train_handler(&chunk, &mut model, 8);
+        assert_eq!(Mask::from_model(&model).sparsity(), mask.sparsity());
+    }
+}
@@ -0,0 +1,219 @@
+use crate::model::Model;
+use crate::util::{split_chunk, to_array2, DataSingle};
+use ndarray::Array2;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+// a reservoir sample of every example added, so old and new data are kept in proportion
+#[derive(Clone, Debug, Default)]
+pub struct ReplayBuffer {
+    pub capacity: usize,
+    pub examples: Vec<DataSingle>,
+    seen: usize,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.examples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.examples.is_empty()
+    }
+
+    pub fn add(&mut self, example: DataSingle) {
+        self.add_with(example, &mut rand::thread_rng());
+    }
+
+    pub fn add_with<R: Rng>(&mut self, example: DataSingle, rng: &mut R) {
+        if let Some(idx) = self.slot(rng) {
+            self.store(idx, example);
+        }
+    }
+
+    pub fn extend_with<R: Rng>(&mut self, examples: &[DataSingle], rng: &mut R) {
+        for example in examples {
+            if let Some(idx) = self.slot(rng) {
+                self.store(idx, example.clone());
+            }
+        }
+    }
+
+    // algorithm R: the n-th example seen replaces a random slot with probability capacity / n
+    fn slot<R: Rng>(&mut self, rng: &mut R) -> Option<usize> {
+        self.seen += 1;
+        if self.examples.len() < self.capacity {
+            Some(self.examples.len())
+        } else {
+            Some(rng.gen_range(0..self.seen)).filter(|idx| *idx < self.capacity)
+        }
+    }
+
+    fn store(&mut self, idx: usize, example: DataSingle) {
+        if idx == self.examples.len() {
+            self.examples.push(example);
+        } else {
+            self.examples[idx] = example;
+        }
+    }
+
+    // only shrinking drops examples, and the reservoir restarts from the ones kept
+    pub fn set_capacity(&mut self, capacity: usize) {
+        if capacity < self.capacity {
+            self.examples.truncate(capacity);
+            self.seen = self.seen.min(capacity);
+        }
+        self.capacity = capacity;
+    }
+
+    pub fn sample_with<R: Rng>(&self, size: usize, rng: &mut R) -> Vec<DataSingle> {
+        self.examples.choose_multiple(rng, size).cloned().collect()
+    }
+}
+
+// elastic weight consolidation: pulls weights that mattered for earlier data back to where they were
+#[derive(Clone, Debug)]
+pub struct Ewc {
+    pub lambda: f64,
+    pub anchor: (Array2<f64>, Array2<f64>),
+    // diagonal of the empirical fisher information
+    pub fisher: (Array2<f64>, Array2<f64>),
+}
+
+impl Ewc {
+    pub fn new(model: &Model, examples: &[DataSingle], lambda: f64) -> Self {
+        let zeros = (
+            Array2::zeros(model.weights.0.dim()),
+            Array2::zeros(model.weights.1.dim()),
+        );
+        let fisher = examples
+            .iter()
+            .map(|x| {
+                let (_, g) = model.gradients2d(&to_array2(vec![x.image.clone()]), &[x.target]);
+                (g.0.mapv(|v| v * v), g.1.mapv(|v| v * v))
+            })
+            .fold(zeros, |a, b| (a.0 + b.0, a.1 + b.1));
+        let n = examples.len().max(1) as f64;
+        Self {
+            lambda,
+            anchor: model.weights.clone(),
+            fisher: (fisher.0 / n, fisher.1 / n),
+        }
+    }
+
+    pub fn penalty(&self, model: &Model) -> f64 {
+        let layer = |w: &Array2<f64>, a: &Array2<f64>, f: &Array2<f64>| {
+            (f * &(w - a).mapv(|x| x * x)).sum()
+        };
+        self.lambda / 2.0
+            * (layer(&model.weights.0, &self.anchor.0, &self.fisher.0)
+                + layer(&model.weights.1, &self.anchor.1, &self.fisher.1))
+    }
+
+    fn gradients(&self, model: &Model) -> (Array2<f64>, Array2<f64>) {
+        (
+            &self.fisher.0 * &(&model.weights.0 - &self.anchor.0) * self.lambda,
+            &self.fisher.1 * &(&model.weights.1 - &self.anchor.1) * self.lambda,
+        )
+    }
+}
+
+// one step on the new examples mixed with up to replay examples from the buffer, which then takes them in
+pub fn train_online<R: Rng>(
+    model: &mut Model,
+    buffer: &mut ReplayBuffer,
+    examples: &[DataSingle],
+    replay: usize,
+    ewc: Option<&Ewc>,
+    rng: &mut R,
+) -> f64 {
+    let mut batch = examples.to_vec();
+    batch.extend(buffer.sample_with(replay, rng));
+    let (images, targets) = split_chunk(&batch);
+    let (mut loss, mut gradients) = model.gradients2d(&to_array2(images), &targets);
+    if let Some(ewc) = ewc {
+        let penalty = ewc.gradients(model);
+        gradients = (gradients.0 + penalty.0, gradients.1 + penalty.1);
+        loss += ewc.penalty(model);
+    }
+    model.update_weights(gradients);
+    buffer.extend_with(examples, rng);
+    loss
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use crate::util::approximate_equal;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn example(target: u8) -> DataSingle {
+        DataSingle {
+            target,
+            image: vec![target as f64 / 10.0; 8],
+        }
+    }
+
+    #[test]
+    fn test_reservoir() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut buffer = ReplayBuffer::new(10);
+        for i in 0..1000 {
+            buffer.add_with(example((i % 10) as u8), &mut rng);
+        }
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(buffer.sample_with(4, &mut rng).len(), 4);
+        assert_eq!(buffer.sample_with(40, &mut rng).len(), 10);
+        buffer.set_capacity(10);
+        assert_eq!(buffer.seen, 1000);
+        buffer.set_capacity(20);
+        assert_eq!((buffer.len(), buffer.seen), (10, 1000));
+        buffer.set_capacity(3);
+        assert_eq!((buffer.len(), buffer.seen), (3, 3));
+    }
+
+    #[test]
+    fn test_train_online() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut model = Model::random_with(8, 4, default_classes(3), (0.1, 0.1), &mut rng);
+        let mut buffer = ReplayBuffer::new(5);
+        buffer.extend_with(&[example(0), example(1)], &mut rng);
+        let loss = train_online(&mut model, &mut buffer, &[example(2)], 2, None, &mut rng);
+        assert!(loss.is_finite());
+        assert_eq!(buffer.len(), 3);
+    }
+
+    #[test]
+    fn test_ewc() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut model = Model::random_with(8, 4, default_classes(3), (0.1, 0.1), &mut rng);
+        let examples = [example(0), example(1), example(2)];
+        let ewc = Ewc::new(&model, &examples, 10.0);
+        assert!(ewc.fisher.0.iter().all(|x| *x >= 0.0));
+        assert!(approximate_equal(ewc.penalty(&model), 0.0, None));
+
+        let mut free = model.clone();
+        let mut buffer = ReplayBuffer::new(0);
+        for _ in 0..20 {
+            train_online(&mut free, &mut buffer, &[example(2)], 0, None, &mut rng);
+            train_online(
+                &mut model,
+                &mut buffer,
+                &[example(2)],
+                0,
+                Some(&ewc),
+                &mut rng,
+            );
+        }
+        // the penalty keeps the consolidated model closer to the anchor
+        assert!(ewc.penalty(&model) < ewc.penalty(&free));
+    }
+}
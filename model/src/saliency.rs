@@ -0,0 +1,170 @@
+use crate::model::Model;
+use crate::util::to_array2;
+use ndarray::{Array2, Axis};
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Saliency {
+    #[default]
+    Gradient,
+    SmoothGrad,
+    IntegratedGradients,
+}
+
+// gradients of the class logit with respect to each input row
+fn gradients(model: &Model, images: Array2<f64>, class: u8) -> Array2<f64> {
+    let (layer1_relu, _) = model.forward_layers(images.view());
+    let mut logit_gradients = Array2::zeros((images.nrows(), model.num_classes()));
+    logit_gradients.column_mut(class as usize).fill(1.0);
+    model.input_gradients(layer1_relu, logit_gradients)
+}
+
+pub fn gradient(model: &Model, image: &[f64], class: u8) -> Vec<f64> {
+    gradients(model, to_array2(vec![image.to_vec()]), class).into_raw_vec()
+}
+
+// SmoothGrad averages the gradient over copies of the image with gaussian noise
+pub fn smooth_grad(
+    model: &Model,
+    image: &[f64],
+    class: u8,
+    samples: usize,
+    sigma: f64,
+) -> Vec<f64> {
+    smooth_grad_with(model, image, class, samples, sigma, &mut rand::thread_rng())
+}
+
+pub fn smooth_grad_with<R: Rng>(
+    model: &Model,
+    image: &[f64],
+    class: u8,
+    samples: usize,
+    sigma: f64,
+    rng: &mut R,
+) -> Vec<f64> {
+    let noisy = (0..samples)
+        .map(|_| image.iter().map(|x| x + sigma * normal(rng)).collect())
+        .collect::<Vec<Vec<f64>>>();
+    gradients(model, to_array2(noisy), class)
+        .mean_axis(Axis(0))
+        .unwrap()
+        .to_vec()
+}
+
+// integrated gradients along the straight path from the baseline (black by default) to the image
+pub fn integrated_gradients(
+    model: &Model,
+    image: &[f64],
+    class: u8,
+    baseline: Option<&[f64]>,
+    steps: usize,
+) -> Vec<f64> {
+    let zeros = vec![0.0; image.len()];
+    let baseline = baseline.unwrap_or(&zeros);
+    let path = (1..=steps)
+        .map(|step| {
+            let alpha = step as f64 / steps as f64;
+            image
+                .iter()
+                .zip(baseline)
+                .map(|(x, b)| b + alpha * (x - b))
+                .collect()
+        })
+        .collect::<Vec<Vec<f64>>>();
+    let mean = gradients(model, to_array2(path), class)
+        .mean_axis(Axis(0))
+        .unwrap();
+    image
+        .iter()
+        .zip(baseline)
+        .zip(mean.iter())
+        .map(|((x, b), g)| (x - b) * g)
+        .collect()
+}
+
+pub fn saliency(model: &Model, image: &[f64], class: u8, method: Saliency) -> Vec<f64> {
+    match method {
+        Saliency::Gradient => gradient(model, image, class),
+        Saliency::SmoothGrad => smooth_grad(model, image, class, 25, 0.15),
+        Saliency::IntegratedGradients => integrated_gradients(model, image, class, None, 32),
+    }
+}
+
+// scales a map into [-1, 1] for display
+pub fn normalize(map: &[f64]) -> Vec<f64> {
+    let max = map.iter().fold(0.0, |a: f64, b| a.max(b.abs()));
+    match max > 0.0 {
+        true => map.iter().map(|x| x / max).collect(),
+        false => map.to_vec(),
+    }
+}
+
+// Box-Muller transform
+pub(crate) fn normal<R: Rng>(rng: &mut R) -> f64 {
+    let u1 = 1.0 - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use crate::util::approximate_equal;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn logit(model: &Model, image: &[f64], class: u8) -> f64 {
+        model
+            .forward_layers(to_array2(vec![image.to_vec()]).view())
+            .1[[0, class as usize]]
+    }
+
+    #[test]
+    fn test_gradient() {
+        let model = Model::random(6, 4, default_classes(3), (0.1, 0.1));
+        let image = vec![0.3, 0.9, 0.1, 0.5, 0.7, 0.2];
+        let res = gradient(&model, &image, 2);
+        assert_eq!(res.len(), 6);
+        for (i, g) in res.iter().enumerate() {
+            let mut shifted = image.clone();
+            shifted[i] += 1e-6;
+            let numeric = (logit(&model, &shifted, 2) - logit(&model, &image, 2)) / 1e-6;
+            assert!(approximate_equal(*g, numeric, Some(1e-4)));
+        }
+    }
+
+    #[test]
+    fn test_smooth_grad() {
+        let model = Model::random(6, 4, default_classes(3), (0.1, 0.1));
+        let image = vec![0.3, 0.9, 0.1, 0.5, 0.7, 0.2];
+        let mut rng = StdRng::seed_from_u64(3);
+        let res = smooth_grad_with(&model, &image, 1, 10, 0.0, &mut rng);
+        let exact = gradient(&model, &image, 1);
+        assert!(res
+            .iter()
+            .zip(exact.iter())
+            .all(|(x, y)| approximate_equal(*x, *y, None)));
+    }
+
+    #[test]
+    fn test_integrated_gradients_completeness() {
+        let model = Model::random(6, 4, default_classes(3), (0.1, 0.1));
+        let image = vec![0.3, 0.9, 0.1, 0.5, 0.7, 0.2];
+        let res = integrated_gradients(&model, &image, 0, None, 512);
+        // the attributions add up to the change in score from the baseline
+        let total = logit(&model, &image, 0) - logit(&model, &[0.0; 6], 0);
+        assert!(approximate_equal(
+            res.iter().sum::<f64>(),
+            total,
+            Some(0.05 * total.abs().max(1.0))
+        ));
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(&[-2.0, 1.0, 0.0]), vec![-1.0, 0.5, 0.0]);
+        assert_eq!(normalize(&[0.0, 0.0]), vec![0.0, 0.0]);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::optimizer::Optimizer;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    #[default]
+    Grid,
+    Random,
+    Hyperband,
+}
+
+impl Strategy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace(['_', '-'], "").as_str() {
+            "grid" => Some(Self::Grid),
+            "random" => Some(Self::Random),
+            "hyperband" | "successivehalving" => Some(Self::Hyperband),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Config {
+    pub learning_rate: f64,
+    pub batch_size: usize,
+    pub hidden: usize,
+    pub optimizer: Optimizer,
+    pub weight_decay: f64,
+}
+
+impl Config {
+    pub fn hyperparameters(&self) -> Vec<(&'static str, f64)> {
+        vec![
+            ("learning_rate", self.learning_rate),
+            ("batch_size", self.batch_size as f64),
+            ("hidden", self.hidden as f64),
+            ("weight_decay", self.weight_decay),
+        ]
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchSpace {
+    pub learning_rates: Vec<f64>,
+    pub batch_sizes: Vec<usize>,
+    pub hiddens: Vec<usize>,
+    pub optimizers: Vec<Optimizer>,
+    pub weight_decays: Vec<f64>,
+}
+
+impl SearchSpace {
+    pub fn grid(&self) -> Vec<Config> {
+        let mut res = Vec::new();
+        for &learning_rate in &self.learning_rates {
+            for &batch_size in &self.batch_sizes {
+                for &hidden in &self.hiddens {
+                    for &optimizer in &self.optimizers {
+                        for &weight_decay in &self.weight_decays {
+                            res.push(Config {
+                                learning_rate,
+                                batch_size,
+                                hidden,
+                                optimizer,
+                                weight_decay,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    // the learning rate is drawn log-uniformly between the smallest and largest given,
+    // everything else from the listed values
+    pub fn sample_with<R: Rng>(&self, rng: &mut R) -> Config {
+        let (low, high) = self
+            .learning_rates
+            .iter()
+            .fold((f64::MAX, f64::MIN), |(l, h), x| (l.min(*x), h.max(*x)));
+        let learning_rate = if low < high {
+            (rng.gen_range(low.ln()..high.ln())).exp()
+        } else {
+            low
+        };
+        Config {
+            learning_rate,
+            batch_size: *self.batch_sizes.choose(rng).unwrap(),
+            hidden: *self.hiddens.choose(rng).unwrap(),
+            optimizer: *self.optimizers.choose(rng).unwrap(),
+            weight_decay: *self.weight_decays.choose(rng).unwrap(),
+        }
+    }
+}
+
+// one evaluation of a configuration on the validation split after that many iterations
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Trial {
+    pub config: Config,
+    pub iterations: usize,
+    pub accuracy: f64,
+}
+
+pub struct Outcome<T> {
+    pub trials: Vec<Trial>,
+    // the best configuration among those trained the longest, with its state
+    pub best: Option<(Config, T, f64)>,
+}
+
+// train(config, state, iterations) carries on training from state (None starts fresh) for
+// that many more iterations, and returns the new state and its validation accuracy
+pub fn evaluate_all<T, F>(configs: Vec<Config>, iterations: usize, mut train: F) -> Outcome<T>
+where
+    F: FnMut(&Config, Option<T>, usize) -> (T, f64),
+{
+    successive_halving(configs, iterations, iterations, 1, &mut train)
+}
+
+// trains every configuration for min_iterations, then keeps the best 1/eta and trains them
+// eta times longer, until one is left or max_iterations is reached
+pub fn successive_halving<T, F>(
+    configs: Vec<Config>,
+    min_iterations: usize,
+    max_iterations: usize,
+    eta: usize,
+    train: &mut F,
+) -> Outcome<T>
+where
+    F: FnMut(&Config, Option<T>, usize) -> (T, f64),
+{
+    let mut trials = Vec::new();
+    let mut runs: Vec<(Config, Option<T>, f64)> =
+        configs.into_iter().map(|x| (x, None, 0.0)).collect();
+    let mut done = 0;
+    let mut budget = min_iterations.min(max_iterations);
+    loop {
+        runs = runs
+            .into_iter()
+            .map(|(config, state, _)| {
+                let (state, accuracy) = train(&config, state, budget - done);
+                trials.push(Trial {
+                    config: config.clone(),
+                    iterations: budget,
+                    accuracy,
+                });
+                (config, Some(state), accuracy)
+            })
+            .collect();
+        runs.sort_by(|a, b| b.2.total_cmp(&a.2));
+        if eta <= 1 || runs.len() <= 1 || budget >= max_iterations {
+            break;
+        }
+        runs.truncate((runs.len() / eta).max(1));
+        done = budget;
+        budget = (budget * eta).min(max_iterations);
+    }
+    let best = runs
+        .into_iter()
+        .next()
+        .map(|(config, state, accuracy)| (config, state.unwrap(), accuracy));
+    Outcome { trials, best }
+}
+
+// runs successive halving in brackets that trade more configurations for fewer iterations each
+pub fn hyperband<T, F, R>(
+    space: &SearchSpace,
+    max_iterations: usize,
+    eta: usize,
+    rng: &mut R,
+    mut train: F,
+) -> Outcome<T>
+where
+    F: FnMut(&Config, Option<T>, usize) -> (T, f64),
+    R: Rng,
+{
+    let eta = eta.max(2);
+    let mut brackets = 0;
+    while max_iterations / eta.pow(brackets + 1) > 0 {
+        brackets += 1;
+    }
+    let mut res = Outcome {
+        trials: Vec::new(),
+        best: None,
+    };
+    for s in (0..=brackets).rev() {
+        let count = ((brackets + 1) as f64 / (s + 1) as f64 * eta.pow(s) as f64).ceil() as usize;
+        let configs = (0..count).map(|_| space.sample_with(rng)).collect();
+        let bracket = successive_halving(
+            configs,
+            max_iterations / eta.pow(s),
+            max_iterations,
+            eta,
+            &mut train,
+        );
+        res.trials.extend(bracket.trials);
+        if let Some(best) = bracket.best {
+            if res.best.as_ref().is_none_or(|x| best.2 > x.2) {
+                res.best = Some(best);
+            }
+        }
+    }
+    res
+}
+
+pub fn results_csv(trials: &[Trial]) -> String {
+    let mut res =
+        "learning_rate,batch_size,hidden,optimizer,weight_decay,iterations,accuracy\n".to_string();
+    for trial in trials {
+        let config = &trial.config;
+        res.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            config.learning_rate,
+            config.batch_size,
+            config.hidden,
+            config.optimizer.name(),
+            config.weight_decay,
+            trial.iterations,
+            trial.accuracy
+        ));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn space() -> SearchSpace {
+        SearchSpace {
+            learning_rates: vec![0.01, 0.1],
+            batch_sizes: vec![32, 128],
+            hiddens: vec![64],
+            optimizers: vec![Optimizer::Sgd, Optimizer::Adam],
+            weight_decays: vec![0.0],
+        }
+    }
+
+    // accuracy grows with training and peaks for a learning rate of 0.1 with adam
+    fn train(config: &Config, state: Option<usize>, iterations: usize) -> (usize, f64) {
+        let total = state.unwrap_or(0) + iterations;
+        let quality = 1.0 - (config.learning_rate.ln() - 0.1f64.ln()).abs() / 10.0
+            + if config.optimizer == Optimizer::Adam {
+                0.1
+            } else {
+                0.0
+            };
+        (total, quality * (1.0 - 1.0 / (total as f64 + 1.0)))
+    }
+
+    #[test]
+    fn test_grid() {
+        let configs = space().grid();
+        assert_eq!(configs.len(), 8);
+        let outcome = evaluate_all(configs, 10, train);
+        assert_eq!(outcome.trials.len(), 8);
+        let (config, state, _) = outcome.best.unwrap();
+        assert_eq!(config.learning_rate, 0.1);
+        assert_eq!(config.optimizer, Optimizer::Adam);
+        assert_eq!(state, 10);
+        assert_eq!(results_csv(&outcome.trials).lines().count(), 9);
+    }
+
+    #[test]
+    fn test_random() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let config = space().sample_with(&mut rng);
+            assert!((0.01..=0.1).contains(&config.learning_rate));
+            assert!([32, 128].contains(&config.batch_size));
+        }
+    }
+
+    #[test]
+    fn test_successive_halving() {
+        let outcome = successive_halving(space().grid(), 3, 27, 3, &mut train);
+        // 8 configs at 3 iterations, 2 at 9, then the winner alone at 27
+        assert_eq!(outcome.trials.len(), 8 + 2 + 1);
+        let (config, state, _) = outcome.best.unwrap();
+        assert_eq!(state, 27);
+        assert_eq!(config.optimizer, Optimizer::Adam);
+    }
+
+    #[test]
+    fn test_hyperband() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let outcome = hyperband(&space(), 27, 3, &mut rng, train);
+        assert!(outcome.trials.iter().all(|x| x.iterations <= 27));
+        assert!(outcome.trials.iter().any(|x| x.iterations == 1));
+        assert_eq!(outcome.best.unwrap().1, 27);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::calibration;
+use crate::model::Model;
+use crate::ood::OodDetector;
+use crate::preprocess::{center, fit, MNIST_BOX_RATIO};
+use crate::util::{argmax, IMAGE_SIDE};
+use serde_derive::{Deserialize, Serialize};
+
+// pixels at or below this are background
+const INK: f64 = 0.1;
+// components with fewer pixels than this are specks
+const MIN_PIXELS: usize = 3;
+// components overlapping horizontally by more than this share of the narrower are one digit
+const MERGE_OVERLAP: f64 = 0.5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Segmentation {
+    // 8-connected strokes, with strokes stacked above each other merged into one digit
+    #[default]
+    ConnectedComponents,
+    // runs of columns with ink, split at empty columns
+    ColumnProjection,
+}
+
+impl Segmentation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace(['_', '-'], "").as_str() {
+            "connectedcomponents" | "components" => Some(Self::ConnectedComponents),
+            "columnprojection" | "columns" => Some(Self::ColumnProjection),
+            _ => None,
+        }
+    }
+}
+
+// the pixels of one digit and its bounding box as (top, bottom, left, right), ends exclusive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub pixels: Vec<usize>,
+    pub bounds: (usize, usize, usize, usize),
+}
+
+impl Segment {
+    fn new(pixels: Vec<usize>, width: usize) -> Self {
+        let rows = pixels.iter().map(|x| x / width);
+        let cols = pixels.iter().map(|x| x % width);
+        let bounds = (
+            rows.clone().min().unwrap_or(0),
+            rows.max().map_or(0, |x| x + 1),
+            cols.clone().min().unwrap_or(0),
+            cols.max().map_or(0, |x| x + 1),
+        );
+        Self { pixels, bounds }
+    }
+
+    fn overlap(&self, other: &Self) -> f64 {
+        let shared =
+            self.bounds.3.min(other.bounds.3) as f64 - self.bounds.2.max(other.bounds.2) as f64;
+        let narrower = (self.bounds.3 - self.bounds.2).min(other.bounds.3 - other.bounds.2);
+        shared / narrower.max(1) as f64
+    }
+
+    // the digit alone, fitted to the MNIST box and centred by mass in a 28x28 frame
+    pub fn normalize(&self, image: &[f64], width: usize) -> Vec<f64> {
+        let (top, bottom, left, right) = self.bounds;
+        let shape = (bottom - top, right - left);
+        let mut cropped = vec![0.0; shape.0 * shape.1];
+        for pixel in &self.pixels {
+            let (row, col) = (pixel / width - top, pixel % width - left);
+            cropped[row * shape.1 + col] = image[*pixel];
+        }
+        let box_size = (IMAGE_SIDE as f64 * MNIST_BOX_RATIO).round() as usize;
+        let (resized, shape) = fit(&cropped, shape, box_size);
+        center(&resized, shape, IMAGE_SIDE)
+            .into_iter()
+            .map(|x| x.clamp(0.0, 1.0))
+            .collect()
+    }
+}
+
+// the digits of an image laid out in rows of width pixels, from left to right
+pub fn segment(image: &[f64], width: usize, method: Segmentation) -> Vec<Segment> {
+    let mut res = match method {
+        Segmentation::ConnectedComponents => components(image, width),
+        Segmentation::ColumnProjection => columns(image, width),
+    };
+    res.retain(|x| x.pixels.len() >= MIN_PIXELS);
+    res.sort_by_key(|x| x.bounds.2);
+    res
+}
+
+fn components(image: &[f64], width: usize) -> Vec<Segment> {
+    let height = image.len() / width;
+    let mut seen = vec![false; image.len()];
+    let mut found: Vec<Segment> = Vec::new();
+    for start in 0..image.len() {
+        if seen[start] || image[start] <= INK {
+            continue;
+        }
+        seen[start] = true;
+        let mut stack = vec![start];
+        let mut pixels = Vec::new();
+        while let Some(pixel) = stack.pop() {
+            pixels.push(pixel);
+            let (row, col) = ((pixel / width) as i64, (pixel % width) as i64);
+            for (dr, dc) in (-1..=1).flat_map(|r| (-1..=1).map(move |c| (r, c))) {
+                let (r, c) = (row + dr, col + dc);
+                if r < 0 || c < 0 || r >= height as i64 || c >= width as i64 {
+                    continue;
+                }
+                let next = r as usize * width + c as usize;
+                if !seen[next] && image[next] > INK {
+                    seen[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        found.push(Segment::new(pixels, width));
+    }
+    // strokes lifted mid digit, like the bar of a 5, end up as separate components
+    found.sort_by_key(|x| x.bounds.2);
+    let mut res: Vec<Segment> = Vec::new();
+    for segment in found {
+        match res.iter_mut().find(|x| x.overlap(&segment) > MERGE_OVERLAP) {
+            Some(merged) => {
+                let mut pixels = std::mem::take(&mut merged.pixels);
+                pixels.extend(segment.pixels);
+                *merged = Segment::new(pixels, width);
+            }
+            None => res.push(segment),
+        }
+    }
+    res
+}
+
+fn columns(image: &[f64], width: usize) -> Vec<Segment> {
+    let inked = |col: usize| image.iter().skip(col).step_by(width).any(|x| *x > INK);
+    let mut res = Vec::new();
+    let mut start = None;
+    for col in 0..=width {
+        match (start, col < width && inked(col)) {
+            (None, true) => start = Some(col),
+            (Some(left), false) => {
+                let pixels = (0..image.len())
+                    .filter(|i| (left..col).contains(&(i % width)) && image[*i] > INK)
+                    .collect();
+                res.push(Segment::new(pixels, width));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    res
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Digit {
+    pub prediction: u8,
+    pub class: String,
+    pub confidence: f64,
+    pub recognised: bool,
+    pub bounds: (usize, usize, usize, usize),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Reading {
+    // the class names joined, with ? for unrecognised digits
+    pub text: String,
+    pub digits: Vec<Digit>,
+}
+
+pub fn read(
+    model: &Model,
+    image: &[f64],
+    width: usize,
+    method: Segmentation,
+    temperature: f64,
+    ood: Option<&OodDetector>,
+) -> Reading {
+    let segments = segment(image, width, method);
+    if segments.is_empty() {
+        return Reading {
+            text: String::new(),
+            digits: Vec::new(),
+        };
+    }
+    let images = segments
+        .iter()
+        .map(|x| x.normalize(image, width))
+        .collect::<Vec<Vec<f64>>>();
+    let recognised = match ood {
+        Some(ood) => ood.recognised(model, images.clone()),
+        None => vec![true; images.len()],
+    };
+    let digits = calibration::probabilities(model, images, temperature)
+        .into_iter()
+        .zip(recognised)
+        .zip(segments)
+        .map(|((probabilities, recognised), segment)| {
+            let prediction = argmax(probabilities.iter());
+            Digit {
+                prediction,
+                class: model.class_name(prediction),
+                confidence: probabilities[prediction as usize],
+                recognised,
+                bounds: segment.bounds,
+            }
+        })
+        .collect::<Vec<Digit>>();
+    Reading {
+        text: digits
+            .iter()
+            .map(|x| match x.recognised {
+                true => x.class.clone(),
+                false => "?".to_string(),
+            })
+            .collect(),
+        digits,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+
+    // a 10x30 canvas with a vertical bar, a T drawn in two separate strokes and a box
+    fn canvas() -> Vec<f64> {
+        let width = 30;
+        let mut image = vec![0.0; 10 * width];
+        for row in 2..8 {
+            image[row * width + 3] = 1.0;
+            image[row * width + 14] = 1.0;
+        }
+        image[11..18].fill(1.0);
+        for i in 22..28 {
+            image[2 * width + i] = 1.0;
+            image[7 * width + i] = 1.0;
+            image[(i - 20) * width + 22] = 1.0;
+            image[(i - 20) * width + 27] = 1.0;
+        }
+        image
+    }
+
+    #[test]
+    fn test_segment() {
+        let image = canvas();
+        for method in [
+            Segmentation::ConnectedComponents,
+            Segmentation::ColumnProjection,
+        ] {
+            let segments = segment(&image, 30, method);
+            assert_eq!(segments.len(), 3, "{:?}", method);
+            assert_eq!(segments[0].bounds, (2, 8, 3, 4));
+            assert_eq!(segments[1].bounds, (0, 8, 11, 18));
+            assert_eq!(segments[2].bounds, (2, 8, 22, 28));
+        }
+        // a lone speck is ignored
+        let mut specked = image.clone();
+        specked[9 * 30 + 8] = 1.0;
+        assert_eq!(
+            segment(&specked, 30, Segmentation::ConnectedComponents).len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let image = canvas();
+        let segments = segment(&image, 30, Segmentation::ConnectedComponents);
+        let digit = segments[2].normalize(&image, 30);
+        assert_eq!(digit.len(), IMAGE_SIDE * IMAGE_SIDE);
+        let bounds = crate::preprocess::bounding_box(&digit, IMAGE_SIDE).unwrap();
+        // fitted to the 20 pixel box
+        assert_eq!(bounds.1 - bounds.0, 20);
+    }
+
+    #[test]
+    fn test_read() {
+        let model = Model::random(IMAGE_SIDE * IMAGE_SIDE, 8, default_classes(10), (0.1, 0.1));
+        let image = canvas();
+        let reading = read(&model, &image, 30, Segmentation::default(), 1.0, None);
+        assert_eq!(reading.digits.len(), 3);
+        assert_eq!(reading.text.len(), 3);
+        assert!(reading
+            .digits
+            .iter()
+            .all(|x| (0.0..=1.0).contains(&x.confidence)));
+        let empty = read(&model, &[0.0; 300], 30, Segmentation::default(), 1.0, None);
+        assert!(empty.text.is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::activations::ActivationFunctions;
+use crate::model::Model;
+use crate::util::argmax;
+use ndarray::{Array1, Array2};
+use serde_derive::{Deserialize, Serialize};
+
+// compressed sparse row storage, values are kept as f32 to keep the serialised form small
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SparseMatrix {
+    pub shape: (usize, usize),
+    pub indptr: Vec<u32>,
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+impl SparseMatrix {
+    pub fn from_dense(dense: &Array2<f64>) -> Self {
+        let mut indptr = vec![0];
+        let mut indices = Vec::new();
+        let mut values = Vec::new();
+        for row in dense.rows() {
+            for (col, value) in row.iter().enumerate() {
+                if *value != 0.0 {
+                    indices.push(col as u32);
+                    values.push(*value as f32);
+                }
+            }
+            indptr.push(indices.len() as u32);
+        }
+        Self {
+            shape: dense.dim(),
+            indptr,
+            indices,
+            values,
+        }
+    }
+
+    pub fn to_dense(&self) -> Array2<f64> {
+        let mut res = Array2::zeros(self.shape);
+        for row in 0..self.shape.0 {
+            for i in self.indptr[row] as usize..self.indptr[row + 1] as usize {
+                res[[row, self.indices[i] as usize]] = self.values[i] as f64;
+            }
+        }
+        res
+    }
+
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn density(&self) -> f64 {
+        self.nnz() as f64 / (self.shape.0 * self.shape.1) as f64
+    }
+
+    // input row vector times the matrix, skipping zero inputs as well as zero weights
+    pub fn vecmat(&self, input: &[f64]) -> Array1<f64> {
+        let mut res = Array1::zeros(self.shape.1);
+        for (row, x) in input.iter().enumerate() {
+            if *x == 0.0 {
+                continue;
+            }
+            for i in self.indptr[row] as usize..self.indptr[row + 1] as usize {
+                res[self.indices[i] as usize] += x * self.values[i] as f64;
+            }
+        }
+        res
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SparseWeights {
+    pub weights: (SparseMatrix, SparseMatrix),
+    #[serde(default)]
+    pub classes: Vec<String>,
+}
+
+impl SparseWeights {
+    pub fn from_model(model: &Model) -> Self {
+        Self {
+            weights: (
+                SparseMatrix::from_dense(&model.weights.0),
+                SparseMatrix::from_dense(&model.weights.1),
+            ),
+            classes: model.classes.clone(),
+        }
+    }
+
+    pub fn to_model(&self, learning_rates: (f64, f64)) -> Model {
+        Model {
+            weights: (self.weights.0.to_dense(), self.weights.1.to_dense()),
+            learning_rates,
+            classes: self.classes.clone(),
+        }
+    }
+
+    pub fn infer1d(&self, input: Vec<f64>) -> u8 {
+        let layer = ActivationFunctions::relu1d(self.weights.0.vecmat(&input));
+        let layer = self.weights.1.vecmat(layer.as_slice().unwrap());
+        argmax(layer.iter())
+    }
+
+    pub fn infer2d(&self, input: Vec<Vec<f64>>) -> Vec<u8> {
+        input.into_iter().map(|x| self.infer1d(x)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::default_classes;
+    use crate::util::random_dist;
+
+    #[test]
+    fn test_round_trip() {
+        let dense = Array2::from_shape_vec((2, 3), vec![0.0, 1.5, 0.0, -2.0, 0.0, 0.25]).unwrap();
+        let sparse = SparseMatrix::from_dense(&dense);
+        assert_eq!(sparse.indptr, vec![0, 1, 3]);
+        assert_eq!(sparse.indices, vec![1, 0, 2]);
+        assert_eq!(sparse.nnz(), 3);
+        assert_eq!(sparse.to_dense(), dense);
+        assert_eq!(sparse.vecmat(&[2.0, 1.0]).to_vec(), vec![-2.0, 3.0, 0.25]);
+    }
+
+    #[test]
+    fn test_sparse_inference() {
+        let mut model = Model::random(784, 128, default_classes(10), (0.1, 0.1));
+        model
+            .weights
+            .0
+            .mapv_inplace(|x| if x.abs() < 0.8 { 0.0 } else { x });
+        let sparse = SparseWeights::from_model(&model);
+        let dense = sparse.to_model((0.1, 0.1));
+        let input = random_dist(32, 784);
+        assert_eq!(sparse.infer2d(input.clone()), dense.infer2d(input.clone()));
+        assert_eq!(
+            sparse.infer1d(input[0].clone()),
+            dense.infer1d(input[0].clone())
+        );
+
+        let sparse_json = serde_json::to_string(&sparse).unwrap();
+        let dense_json = serde_json::to_string(&model.export()).unwrap();
+        assert!(sparse_json.len() * 2 < dense_json.len());
+        let loaded: SparseWeights = serde_json::from_str(&sparse_json).unwrap();
+        assert_eq!(loaded, sparse);
+    }
+
+    #[test]
+    fn test_sparse_inference_negative_logits() {
+        let model = Model::new((vec![vec![1.0]], vec![vec![-3.0, -1.0, -2.0]]), (0.1, 0.1));
+        assert_eq!(SparseWeights::from_model(&model).infer1d(vec![1.0]), 1);
+    }
+}
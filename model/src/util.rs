@@ -1,16 +1,29 @@
+use crate::adversarial::Attack;
+use crate::ensemble::Combine;
 use crate::model::Model;
+use crate::ood::OodDetector;
+use crate::segment::Segmentation;
+use ndarray::Array2;
 use rand::distributions::uniform;
 use rand::seq::SliceRandom;
 use rand::Rng;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
 
+pub const IMAGE_SIDE: usize = 28;
+pub const IMAGE_SIZE: usize = IMAGE_SIDE * IMAGE_SIDE;
+pub const HIDDEN_SIZE: usize = 128;
+
 pub fn random_dist(m: u32, h: u32) -> Vec<Vec<f64>> {
+    random_dist_with(m, h, &mut rand::thread_rng())
+}
+
+pub fn random_dist_with<R: Rng>(m: u32, h: u32, rng: &mut R) -> Vec<Vec<f64>> {
     let mut weights = Vec::new();
     for _ in 0..m {
         let mut row = Vec::new();
         for _ in 0..h {
-            let mut rng = rand::thread_rng();
             row.push(rng.sample(uniform::Uniform::new(-1.0, 1.0)))
         }
         weights.push(row);
@@ -18,19 +31,56 @@ pub fn random_dist(m: u32, h: u32) -> Vec<Vec<f64>> {
     weights
 }
 
-pub fn random_int(m: u32, h: u32) -> Vec<Vec<u8>> {
+pub fn random_int(m: u32, h: u32, classes: u8) -> Vec<Vec<u8>> {
     let mut weights = Vec::new();
     for _ in 0..m {
         let mut row = Vec::new();
         for _ in 0..h {
             let mut rng = rand::thread_rng();
-            row.push((rng.sample(uniform::Uniform::new(0, 10)) as f64).round() as u8);
+            row.push((rng.sample(uniform::Uniform::new(0, classes)) as f64).round() as u8);
         }
         weights.push(row);
     }
     weights
 }
 
+pub fn to_array2(input: Vec<Vec<f64>>) -> Array2<f64> {
+    Array2::from_shape_vec(
+        (input.len(), input[0].len()),
+        input.into_iter().flatten().collect(),
+    )
+    .unwrap()
+}
+
+pub fn one_hot(target: &[u8], classes: usize) -> Array2<f64> {
+    let mut res = Array2::zeros((target.len(), classes));
+    for (i, t) in target.iter().enumerate() {
+        res[[i, *t as usize]] = 1.0;
+    }
+    res
+}
+
+pub fn split_chunk(chunk: &[DataSingle]) -> (Vec<Vec<f64>>, Vec<u8>) {
+    chunk.iter().map(|x| (x.image.clone(), x.target)).unzip()
+}
+
+// starts below every value, since logits and log probabilities can all be negative
+pub fn argmax<'a, I: Iterator<Item = &'a f64>>(values: I) -> u8 {
+    values
+        .enumerate()
+        .fold(
+            (0, f64::NEG_INFINITY),
+            |(max_index, max_value), (index, value)| {
+                if value > &max_value {
+                    (index, *value)
+                } else {
+                    (max_index, max_value)
+                }
+            },
+        )
+        .0 as u8
+}
+
 pub fn approximate_equal(x: f64, y: f64, bound: Option<f64>) -> bool {
     match bound {
         Some(bound) => (x - y).abs() < bound,
@@ -41,16 +91,155 @@ pub fn approximate_equal(x: f64, y: f64, bound: Option<f64>) -> bool {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Weights {
     pub weights: (Vec<Vec<f64>>, Vec<Vec<f64>>),
+    #[serde(default)]
+    pub classes: Vec<String>,
+    #[serde(default)]
+    pub card: Option<ModelCard>,
 }
 
 impl PartialEq for Weights {
     fn eq(&self, other: &Self) -> bool {
-        self.weights == other.weights
+        self.weights == other.weights && self.classes == other.classes && self.card == other.card
     }
 }
 
 impl Eq for Weights {}
 
+impl Weights {
+    // 1 when the weights were never calibrated
+    pub fn temperature(&self) -> f64 {
+        self.card
+            .as_ref()
+            .and_then(|x| x.temperature)
+            .unwrap_or(1.0)
+    }
+
+    // checks the matrices fit together and the card describes them
+    pub fn check(&self) -> Result<(), String> {
+        let (input, output) = &self.weights;
+        let hidden = input.first().map_or(0, |x| x.len());
+        let classes = output.first().map_or(0, |x| x.len());
+        if hidden == 0 || classes == 0 {
+            return Err("weights have no hidden units or classes".to_string());
+        }
+        if input.iter().any(|x| x.len() != hidden) || output.iter().any(|x| x.len() != classes) {
+            return Err("weights have rows of different lengths".to_string());
+        }
+        if output.len() != hidden {
+            return Err(format!(
+                "{} hidden units feed {} output rows",
+                hidden,
+                output.len()
+            ));
+        }
+        if !self.classes.is_empty() && self.classes.len() != classes {
+            return Err(format!(
+                "{} class names for {} classes",
+                self.classes.len(),
+                classes
+            ));
+        }
+        match &self.card {
+            Some(card) if card.layers != [input.len(), hidden, classes] => Err(format!(
+                "model card has layers {:?} but the weights have {:?}",
+                card.layers,
+                [input.len(), hidden, classes]
+            )),
+            Some(card) if card.input_size() != input.len() => Err(format!(
+                "model card expects {:?} inputs but the weights take {}",
+                card.input_shape,
+                input.len()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // checks the matrices and the card against the size of the inputs about to be fed in
+    pub fn check_input_size(&self, size: usize) -> Result<(), String> {
+        if self.weights.0.len() != size {
+            return Err(format!(
+                "weights take {} inputs but got {}",
+                self.weights.0.len(),
+                size
+            ));
+        }
+        match &self.card {
+            Some(card) if card.input_size() != size => Err(format!(
+                "model card expects {:?} inputs but got {}",
+                card.input_shape, size
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ModelCard {
+    // layer widths from the input to the output
+    pub layers: Vec<usize>,
+    pub activations: Vec<String>,
+    pub input_shape: (usize, usize),
+    #[serde(default)]
+    pub preprocessing: Vec<String>,
+    #[serde(default)]
+    pub dataset: Option<String>,
+    #[serde(default)]
+    pub dataset_hash: Option<String>,
+    #[serde(default)]
+    pub hyperparameters: BTreeMap<String, f64>,
+    // choices that aren't numbers, like the optimizer
+    #[serde(default)]
+    pub settings: BTreeMap<String, String>,
+    #[serde(default)]
+    pub accuracy: Option<f64>,
+    // fitted on validation data, the logits are divided by it before the softmax
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    // rejects inputs that don't look like the training data
+    #[serde(default)]
+    pub ood: Option<OodDetector>,
+}
+
+impl ModelCard {
+    pub fn from_model(model: &Model) -> Self {
+        let input = model.input_size();
+        let side = (input as f64).sqrt() as usize;
+        Self {
+            layers: vec![input, model.weights.0.ncols(), model.num_classes()],
+            activations: vec!["relu".to_string(), "logsoftmax".to_string()],
+            input_shape: match side * side == input {
+                true => (side, side),
+                false => (1, input),
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.input_shape.0 * self.input_shape.1
+    }
+}
+
+// FNV-1a over every label and pixel, stable across builds unlike std's hasher
+pub fn dataset_hash(data: &Data) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let bytes = data.data.iter().flat_map(|x| {
+        std::iter::once(x.target).chain(x.image.iter().flat_map(|p| p.to_bits().to_le_bytes()))
+    });
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WeightUpdate {
+    pub base_version: u64,
+    pub delta: (Vec<Vec<f64>>, Vec<Vec<f64>>),
+    pub samples: usize,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DataSingle {
     pub target: u8,
@@ -79,30 +268,210 @@ pub struct Data {
     pub data: Vec<DataSingle>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Activations {
+    pub hidden: Vec<f64>,
+    pub logits: Vec<f64>,
+    pub log_probabilities: Vec<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImagesInfo {
+    pub images: Vec<Vec<f64>>,
+}
+
+// a canvas of several digits side by side, laid out in rows of width pixels
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NumberInfo {
+    pub image: Vec<f64>,
+    pub width: usize,
+    #[serde(default)]
+    pub segmentation: Segmentation,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DataInfo {
     pub block: usize,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DistillInfo {
+    #[serde(default = "DistillInfo::default_temperature")]
+    pub temperature: f64,
+    #[serde(default = "DistillInfo::default_alpha")]
+    pub alpha: f64,
+    #[serde(default = "DistillInfo::default_hidden")]
+    pub hidden: usize,
+    #[serde(default = "DistillInfo::default_teacher_hidden")]
+    pub teacher_hidden: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnsembleInfo {
+    pub versions: Vec<u64>,
+    #[serde(default)]
+    pub member_weights: Vec<f64>,
+    #[serde(default)]
+    pub combine: Combine,
+    pub images: Vec<Vec<f64>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PruneInfo {
+    #[serde(default)]
+    pub sparsity: f64,
+    #[serde(default)]
+    pub hidden: Option<usize>,
+    #[serde(default)]
+    pub fine_tune_iters: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RobustnessInfo {
+    #[serde(default)]
+    pub attack: Attack,
+    #[serde(default = "RobustnessInfo::default_epsilons")]
+    pub epsilons: Vec<f64>,
+    #[serde(default = "RobustnessInfo::default_samples")]
+    pub samples: usize,
+    #[serde(default = "RobustnessInfo::default_steps")]
+    pub steps: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VaeInfo {
+    #[serde(default = "VaeInfo::default_hidden")]
+    pub hidden: usize,
+    #[serde(default = "VaeInfo::default_latent")]
+    pub latent: usize,
+    #[serde(default = "VaeInfo::default_iterations")]
+    pub iterations: usize,
+    #[serde(default = "VaeInfo::default_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "VaeInfo::default_learning_rate")]
+    pub learning_rate: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClassifierInfo {
+    pub kind: String,
+    #[serde(default = "ClassifierInfo::default_k")]
+    pub k: usize,
+    #[serde(default = "ClassifierInfo::default_hidden")]
+    pub hidden: usize,
+    #[serde(default = "ClassifierInfo::default_iterations")]
+    pub iterations: usize,
+    #[serde(default = "ClassifierInfo::default_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "ClassifierInfo::default_learning_rate")]
+    pub learning_rate: f64,
+    #[serde(default)]
+    pub l2: f64,
+    // examples from the validation split to evaluate on
+    #[serde(default = "ClassifierInfo::default_samples")]
+    pub samples: usize,
+}
+
+impl DistillInfo {
+    fn default_temperature() -> f64 {
+        4.0
+    }
+
+    fn default_alpha() -> f64 {
+        0.1
+    }
+
+    fn default_hidden() -> usize {
+        32
+    }
+
+    fn default_teacher_hidden() -> usize {
+        512
+    }
+}
+
+impl VaeInfo {
+    fn default_hidden() -> usize {
+        256
+    }
+
+    fn default_latent() -> usize {
+        16
+    }
+
+    fn default_iterations() -> usize {
+        3000
+    }
+
+    fn default_batch_size() -> usize {
+        64
+    }
+
+    fn default_learning_rate() -> f64 {
+        0.001
+    }
+}
+
+impl ClassifierInfo {
+    fn default_k() -> usize {
+        5
+    }
+
+    fn default_hidden() -> usize {
+        128
+    }
+
+    fn default_iterations() -> usize {
+        1000
+    }
+
+    fn default_batch_size() -> usize {
+        64
+    }
+
+    fn default_learning_rate() -> f64 {
+        0.1
+    }
+
+    fn default_samples() -> usize {
+        1000
+    }
+}
+
+impl RobustnessInfo {
+    fn default_epsilons() -> Vec<f64> {
+        vec![0.0, 0.05, 0.1, 0.15, 0.2, 0.3]
+    }
+
+    fn default_samples() -> usize {
+        1000
+    }
+
+    fn default_steps() -> usize {
+        10
+    }
+}
+
 pub fn get_sample_block(data: &Data, size: usize) -> Vec<DataSingle> {
-    let mut rng = rand::thread_rng();
-    let mut data = data.clone();
-    data.data.shuffle(&mut rng);
-    data.data[0..size].to_vec()
+    get_sample_block_with(data, size, &mut rand::thread_rng())
+}
+
+pub fn get_sample_block_with<R: Rng>(data: &Data, size: usize, rng: &mut R) -> Vec<DataSingle> {
+    data.data.choose_multiple(rng, size).cloned().collect()
 }
 
 pub fn train_handler_wrapper(data: &Data, model: &mut Model, batch_size: usize) -> (f64, f64) {
-    let chunk = get_sample_block(&data, batch_size);
+    let chunk = get_sample_block(data, batch_size);
     train_handler(&chunk, model, batch_size)
 }
 
-pub fn train_handler(chunk: &Vec<DataSingle>, model: &mut Model, batch_size: usize) -> (f64, f64) {
+pub fn train_handler(chunk: &[DataSingle], model: &mut Model, batch_size: usize) -> (f64, f64) {
     let (images, targets): (Vec<Vec<f64>>, Vec<u8>) =
         chunk
-            .into_iter()
+            .iter()
             .fold((Vec::new(), Vec::new()), |(mut images, mut targets), x| {
                 images.push(x.image.clone());
-                targets.push(x.target as u8);
+                targets.push(x.target);
                 (images, targets)
             });
     let accuracy = model
@@ -117,3 +486,68 @@ pub fn train_handler(chunk: &Vec<DataSingle>, model: &mut Model, batch_size: usi
 
     (loss, accuracy)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_argmax() {
+        assert_eq!(argmax([0.1, 0.7, 0.2].iter()), 1);
+        assert_eq!(argmax([-3.0, -0.5, -2.0].iter()), 1);
+        assert_eq!(argmax([1.0, 1.0].iter()), 0);
+    }
+
+    #[test]
+    fn test_model_card() {
+        let model = Model::random(784, 16, crate::dataset::default_classes(10), (0.1, 0.1));
+        let card = ModelCard::from_model(&model);
+        assert_eq!(card.layers, vec![784, 16, 10]);
+        assert_eq!(card.input_shape, (28, 28));
+        let mut weights = model.export();
+        assert!(weights.check_input_size(784).is_ok());
+        assert!(weights.check_input_size(100).is_err());
+        weights.card = Some(ModelCard {
+            input_shape: (20, 20),
+            ..card
+        });
+        assert!(weights.check_input_size(784).is_err());
+
+        let json = r#"{"weights": [[[1.0]], [[1.0]]]}"#;
+        let old: Weights = serde_json::from_str(json).unwrap();
+        assert_eq!(old.card, None);
+    }
+
+    #[test]
+    fn test_weights_check() {
+        let model = Model::random(4, 3, crate::dataset::default_classes(2), (0.1, 0.1));
+        let mut weights = model.export();
+        weights.card = Some(ModelCard::from_model(&model));
+        assert!(weights.check().is_ok());
+        let mut wide = weights.clone();
+        wide.card.as_mut().unwrap().layers[1] = 5;
+        assert!(wide.check().is_err());
+        let mut ragged = weights.clone();
+        ragged.weights.0[1].pop();
+        assert!(ragged.check().is_err());
+        let mut short = weights.clone();
+        short.weights.1.pop();
+        assert!(short.check().is_err());
+        let mut named = weights;
+        named.classes.push("extra".to_string());
+        assert!(named.check().is_err());
+    }
+
+    #[test]
+    fn test_dataset_hash() {
+        let data = |target: u8| Data {
+            data: vec![DataSingle {
+                target,
+                image: vec![0.0, 0.5],
+            }],
+        };
+        assert_eq!(dataset_hash(&data(1)), dataset_hash(&data(1)));
+        assert_ne!(dataset_hash(&data(1)), dataset_hash(&data(2)));
+        assert_eq!(dataset_hash(&data(1)).len(), 16);
+    }
+}
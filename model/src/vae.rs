@@ -0,0 +1,340 @@
+use crate::activations::ActivationFunctions;
+use crate::optimizer::{Optimizer, Trainer};
+use crate::saliency::normal;
+use crate::util::{random_dist_with, to_array2};
+use ndarray::{Array2, Axis};
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
+
+// indices into the parameter list, where each layer's weights are followed by its bias row
+const ENCODER: usize = 0;
+const MEAN: usize = 2;
+const LOG_VARIANCE: usize = 4;
+const DECODER: usize = 6;
+const OUTPUT: usize = 8;
+
+// every matrix as rows, biases as a single row, in the order of the parameter indices
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VaeWeights {
+    pub parameters: Vec<Vec<Vec<f64>>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct VaeLoss {
+    // binary cross entropy of the reconstruction, summed over pixels
+    pub reconstruction: f64,
+    pub kl: f64,
+}
+
+struct Forward {
+    hidden: Array2<f64>,
+    mean: Array2<f64>,
+    log_variance: Array2<f64>,
+    noise: Array2<f64>,
+    latent: Array2<f64>,
+    decoded: Array2<f64>,
+    output: Array2<f64>,
+}
+
+// input -> relu hidden -> gaussian latent -> relu hidden -> sigmoid pixels
+#[derive(Clone, Debug)]
+pub struct Vae {
+    pub parameters: Vec<Array2<f64>>,
+    pub learning_rate: f64,
+    trainer: Trainer,
+}
+
+impl Vae {
+    pub fn random_with<R: Rng>(
+        input: usize,
+        hidden: usize,
+        latent: usize,
+        learning_rate: f64,
+        rng: &mut R,
+    ) -> Self {
+        // glorot uniform
+        let mut layer = |rows: usize, cols: usize| {
+            to_array2(random_dist_with(rows as u32, cols as u32, rng))
+                * (6.0 / (rows + cols) as f64).sqrt()
+        };
+        let parameters = vec![
+            layer(input, hidden),
+            Array2::zeros((1, hidden)),
+            layer(hidden, latent),
+            Array2::zeros((1, latent)),
+            layer(hidden, latent),
+            Array2::zeros((1, latent)),
+            layer(latent, hidden),
+            Array2::zeros((1, hidden)),
+            layer(hidden, input),
+            Array2::zeros((1, input)),
+        ];
+        Self::from_parameters(parameters, learning_rate)
+    }
+
+    pub fn random(input: usize, hidden: usize, latent: usize, learning_rate: f64) -> Self {
+        Self::random_with(
+            input,
+            hidden,
+            latent,
+            learning_rate,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    fn from_parameters(parameters: Vec<Array2<f64>>, learning_rate: f64) -> Self {
+        Self {
+            parameters,
+            learning_rate,
+            trainer: Trainer::new(Optimizer::Adam, 0.0),
+        }
+    }
+
+    pub fn from_weights(weights: VaeWeights, learning_rate: f64) -> Self {
+        Self::from_parameters(
+            weights.parameters.into_iter().map(to_array2).collect(),
+            learning_rate,
+        )
+    }
+
+    pub fn export(&self) -> VaeWeights {
+        VaeWeights {
+            parameters: self
+                .parameters
+                .iter()
+                .map(|x| x.rows().into_iter().map(|r| r.to_vec()).collect())
+                .collect(),
+        }
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.parameters[ENCODER].nrows()
+    }
+
+    pub fn latent_size(&self) -> usize {
+        self.parameters[MEAN].ncols()
+    }
+
+    fn affine(&self, input: &Array2<f64>, weights: usize) -> Array2<f64> {
+        input.dot(&self.parameters[weights]) + &self.parameters[weights + 1]
+    }
+
+    fn encode_layers(&self, input: &Array2<f64>) -> (Array2<f64>, Array2<f64>, Array2<f64>) {
+        let hidden = ActivationFunctions::relu2d(self.affine(input, ENCODER));
+        let mean = self.affine(&hidden, MEAN);
+        let log_variance = self.affine(&hidden, LOG_VARIANCE);
+        (hidden, mean, log_variance)
+    }
+
+    fn decode_layers(&self, latent: &Array2<f64>) -> (Array2<f64>, Array2<f64>) {
+        let decoded = ActivationFunctions::relu2d(self.affine(latent, DECODER));
+        let output = self
+            .affine(&decoded, OUTPUT)
+            .mapv(|x| 1.0 / (1.0 + (-x).exp()));
+        (decoded, output)
+    }
+
+    // the reparameterisation trick: latent = mean + sigma * noise keeps the sample differentiable
+    fn forward<R: Rng>(&self, input: &Array2<f64>, rng: &mut R) -> Forward {
+        let (hidden, mean, log_variance) = self.encode_layers(input);
+        let noise = Array2::from_shape_simple_fn(mean.dim(), || normal(rng));
+        let latent = &mean + &(log_variance.mapv(|x| (0.5 * x).exp()) * &noise);
+        let (decoded, output) = self.decode_layers(&latent);
+        Forward {
+            hidden,
+            mean,
+            log_variance,
+            noise,
+            latent,
+            decoded,
+            output,
+        }
+    }
+
+    // the mean of each image's latent distribution
+    pub fn encode(&self, images: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        let (_, mean, _) = self.encode_layers(&to_array2(images));
+        mean.rows().into_iter().map(|x| x.to_vec()).collect()
+    }
+
+    pub fn decode(&self, latents: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        let (_, output) = self.decode_layers(&to_array2(latents));
+        output.rows().into_iter().map(|x| x.to_vec()).collect()
+    }
+
+    pub fn reconstruct(&self, images: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+        self.decode(self.encode(images))
+    }
+
+    // decodes draws from the standard normal prior
+    pub fn sample_with<R: Rng>(&self, count: usize, rng: &mut R) -> Vec<Vec<f64>> {
+        let latents = (0..count)
+            .map(|_| (0..self.latent_size()).map(|_| normal(rng)).collect())
+            .collect();
+        self.decode(latents)
+    }
+
+    // decodes evenly spaced points on the line between the two images' latent means
+    pub fn interpolate(&self, from: Vec<f64>, to: Vec<f64>, steps: usize) -> Vec<Vec<f64>> {
+        let ends = self.encode(vec![from, to]);
+        let latents = (0..steps)
+            .map(|i| {
+                let t = i as f64 / (steps.max(2) - 1) as f64;
+                ends[0]
+                    .iter()
+                    .zip(&ends[1])
+                    .map(|(a, b)| a + (b - a) * t)
+                    .collect()
+            })
+            .collect();
+        self.decode(latents)
+    }
+
+    pub fn loss_with<R: Rng>(&self, images: Vec<Vec<f64>>, rng: &mut R) -> VaeLoss {
+        let input = to_array2(images);
+        let forward = self.forward(&input, rng);
+        loss(&input, &forward)
+    }
+
+    // one adam step on the mean loss of the batch
+    pub fn train_with<R: Rng>(&mut self, images: Vec<Vec<f64>>, rng: &mut R) -> VaeLoss {
+        let input = to_array2(images);
+        let forward = self.forward(&input, rng);
+        let res = loss(&input, &forward);
+        let gradients = self.gradients(&input, forward);
+        self.update(gradients);
+        res
+    }
+
+    fn gradients(&self, input: &Array2<f64>, forward: Forward) -> Vec<Array2<f64>> {
+        let batch = input.nrows() as f64;
+        let bias = |x: &Array2<f64>| x.sum_axis(Axis(0)).insert_axis(Axis(0));
+        let p = &self.parameters;
+
+        let output = (&forward.output - input) / batch;
+        let decoded = ActivationFunctions::relu_backward2d(
+            forward.decoded.clone(),
+            output.dot(&p[OUTPUT].t()),
+        );
+        let latent = decoded.dot(&p[DECODER].t());
+        // the kl term pulls each latent towards the standard normal
+        let mean = &latent + &(&forward.mean / batch);
+        let sigma = forward.log_variance.mapv(|x| (0.5 * x).exp());
+        let log_variance = &latent * &forward.noise * &sigma * 0.5
+            + forward.log_variance.mapv(|x| (x.exp() - 1.0) * 0.5 / batch);
+        let hidden = ActivationFunctions::relu_backward2d(
+            forward.hidden.clone(),
+            mean.dot(&p[MEAN].t()) + log_variance.dot(&p[LOG_VARIANCE].t()),
+        );
+
+        vec![
+            input.t().dot(&hidden),
+            bias(&hidden),
+            forward.hidden.t().dot(&mean),
+            bias(&mean),
+            forward.hidden.t().dot(&log_variance),
+            bias(&log_variance),
+            forward.latent.t().dot(&decoded),
+            bias(&decoded),
+            forward.decoded.t().dot(&output),
+            bias(&output),
+        ]
+    }
+
+    fn update(&mut self, gradients: Vec<Array2<f64>>) {
+        for (parameter, step) in self.parameters.iter_mut().zip(self.trainer.step(gradients)) {
+            *parameter = &*parameter - &(step * self.learning_rate);
+        }
+    }
+}
+
+fn loss(input: &Array2<f64>, forward: &Forward) -> VaeLoss {
+    let batch = input.nrows() as f64;
+    let clamped = forward.output.mapv(|x| x.clamp(1e-12, 1.0 - 1e-12));
+    let reconstruction = -(input * &clamped.mapv(f64::ln)
+        + &input.mapv(|x| 1.0 - x) * &clamped.mapv(|x| (1.0 - x).ln()))
+        .sum()
+        / batch;
+    let kl = -0.5
+        * (forward.log_variance.mapv(|x| 1.0 + x - x.exp()) - forward.mean.mapv(|x| x * x)).sum()
+        / batch;
+    VaeLoss { reconstruction, kl }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::approximate_equal;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // two kinds of 4x4 images: a lit top half or a lit left half
+    fn images() -> Vec<Vec<f64>> {
+        let top = (0..16).map(|i| (i < 8) as u8 as f64).collect::<Vec<f64>>();
+        let left = (0..16)
+            .map(|i| (i % 4 < 2) as u8 as f64)
+            .collect::<Vec<f64>>();
+        (0..8)
+            .map(|i| {
+                if i % 2 == 0 {
+                    top.clone()
+                } else {
+                    left.clone()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_gradients() {
+        // compares one backpropagated gradient with a finite difference of the loss
+        let mut rng = StdRng::seed_from_u64(1);
+        let vae = Vae::random_with(16, 6, 2, 0.01, &mut rng);
+        let input = to_array2(images());
+        let forward = vae.forward(&input, &mut StdRng::seed_from_u64(9));
+        let gradients = vae.gradients(&input, forward);
+        let total = |vae: &Vae| {
+            let loss = vae.loss_with(images(), &mut StdRng::seed_from_u64(9));
+            loss.reconstruction + loss.kl
+        };
+        for (param, idx) in [
+            (ENCODER, [3, 1]),
+            (LOG_VARIANCE, [2, 1]),
+            (OUTPUT + 1, [0, 5]),
+        ] {
+            let h = 1e-6;
+            let (mut plus, mut minus) = (vae.clone(), vae.clone());
+            plus.parameters[param][idx] += h;
+            minus.parameters[param][idx] -= h;
+            let numeric = (total(&plus) - total(&minus)) / (2.0 * h);
+            assert!(
+                approximate_equal(gradients[param][idx], numeric, Some(1e-4)),
+                "{} {} vs {}",
+                param,
+                gradients[param][idx],
+                numeric
+            );
+        }
+    }
+
+    #[test]
+    fn test_train() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut vae = Vae::random_with(16, 8, 2, 0.01, &mut rng);
+        let first = vae.train_with(images(), &mut rng);
+        for _ in 0..300 {
+            vae.train_with(images(), &mut rng);
+        }
+        let last = vae.loss_with(images(), &mut rng);
+        assert!(last.reconstruction < first.reconstruction);
+
+        let samples = vae.sample_with(3, &mut rng);
+        assert_eq!(samples.len(), 3);
+        assert!(samples.iter().flatten().all(|x| (0.0..=1.0).contains(x)));
+        let path = vae.interpolate(images()[0].clone(), images()[1].clone(), 5);
+        assert_eq!(path.len(), 5);
+        assert_eq!(path[0], vae.reconstruct(vec![images()[0].clone()])[0]);
+
+        let restored = Vae::from_weights(vae.export(), 0.01);
+        assert_eq!(restored.reconstruct(images()), vae.reconstruct(images()));
+    }
+}
@@ -1,4 +1,6 @@
-use model::util::{Data, DataInfo, DataSingle, Weights};
+use model::util::{Data, DataInfo, DataSingle, WeightUpdate, Weights};
+use model::vae::VaeWeights;
+use serde_json::Value;
 use reqwest::Client;
 
 const API_URL: &str = "https://digits-api.sachiniyer.com";
@@ -51,14 +53,61 @@ pub async fn get_block(block_size: usize) -> Data {
     ).unwrap()
 }
 
-pub async fn send_weights(weights: Weights) {
+pub async fn get_federated_version() -> u64 {
+    let client = Client::new();
+    let status: Value = serde_json::from_str(
+        &client
+            .get(format!("{}/federated", API_URL))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    status["version"].as_u64().unwrap()
+}
+
+pub async fn get_version(version: u64) -> Weights {
+    let client = Client::new();
+    serde_json::from_str(
+        &client
+            .get(format!("{}/weights/versions/{}", API_URL, version))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap(),
+    )
+    .unwrap()
+}
+
+// None until a vae has been trained in the API
+pub async fn get_vae() -> Option<VaeWeights> {
+    let client = Client::new();
+    let response = client
+        .get(format!("{}/vae", API_URL))
+        .send()
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    serde_json::from_str(&response.text().await.ok()?).ok()
+}
+
+pub async fn send_update(update: WeightUpdate) -> bool {
     let client = Client::new();
     client
-        .post(format!("{}/weights", API_URL))
-        .json(&weights)
+        .post(format!("{}/federated/updates", API_URL))
+        .json(&update)
         .send()
         .await
-        .unwrap();
+        .unwrap()
+        .status()
+        .is_success()
 }
 
 pub async fn weights_delete() {
@@ -0,0 +1,101 @@
+use crate::{api::get_vae, grid::ImagePreview};
+use model::vae::Vae;
+use yew::prelude::*;
+
+const SAMPLES: usize = 8;
+const STEPS: usize = 8;
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct GeneratorProps {
+    // the current drawing, preprocessed like the training data
+    pub drawing: Vec<f64>,
+}
+
+fn previews(images: &[Vec<f64>]) -> Html {
+    html! {
+        <div class="flex flex-row">
+            { images.iter().map(|x| html! { <ImagePreview image={ x.clone() } /> }).collect::<Html>() }
+        </div>
+    }
+}
+
+// samples, interpolations and reconstructions from the variational autoencoder trained in the API
+#[function_component(Generator)]
+pub fn generator(props: &GeneratorProps) -> Html {
+    let vae_handle = use_state(|| None::<Vae>);
+    let samples_handle = use_state(Vec::<Vec<f64>>::new);
+    let start_handle = use_state(|| None::<Vec<f64>>);
+    let end_handle = use_state(|| None::<Vec<f64>>);
+
+    let load_callback = {
+        let vae_handle = vae_handle.clone();
+        Callback::from(move |_| {
+            let vae_handle = vae_handle.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match get_vae().await {
+                    Some(weights) => vae_handle.set(Some(Vae::from_weights(weights, 0.0))),
+                    None => web_sys::window()
+                        .unwrap()
+                        .alert_with_message("Train a VAE in the API first")
+                        .unwrap(),
+                }
+            });
+        })
+    };
+
+    let sample_callback = {
+        let vae_handle = vae_handle.clone();
+        let samples_handle = samples_handle.clone();
+        Callback::from(move |_| {
+            if let Some(vae) = &*vae_handle {
+                samples_handle.set(vae.sample_with(SAMPLES, &mut rand::thread_rng()));
+            }
+        })
+    };
+
+    let start_callback = {
+        let start_handle = start_handle.clone();
+        let drawing = props.drawing.clone();
+        Callback::from(move |_| start_handle.set(Some(drawing.clone())))
+    };
+
+    let end_callback = {
+        let end_handle = end_handle.clone();
+        let drawing = props.drawing.clone();
+        Callback::from(move |_| end_handle.set(Some(drawing.clone())))
+    };
+
+    let vae = match &*vae_handle {
+        Some(vae) => vae,
+        None => {
+            return html! {
+                <div id="generator">
+                    <button onclick={ load_callback }>{ "Load VAE from API" }</button>
+                </div>
+            }
+        }
+    };
+    html! {
+        <div id="generator">
+            <button onclick={ load_callback }>{ "Load VAE from API" }</button>
+            <div>
+                <p>{ "Reconstruction" }</p>
+                { previews(&vae.reconstruct(vec![props.drawing.clone()])) }
+            </div>
+            <div>
+                <button onclick={ sample_callback }>{ "Sample Digits" }</button>
+                { previews(&samples_handle) }
+            </div>
+            <div>
+                <button onclick={ start_callback }>{ "Interpolate From Drawing" }</button>
+                <button onclick={ end_callback }>{ "Interpolate To Drawing" }</button>
+                {
+                    match (&*start_handle, &*end_handle) {
+                        (Some(start), Some(end)) => previews(&vae.interpolate(start.clone(), end.clone(), STEPS)),
+                        _ => html! {},
+                    }
+                }
+            </div>
+        </div>
+    }
+}
@@ -8,7 +8,9 @@ pub struct GridCellProps {
     pub col: usize,
     pub set_cell: Callback<(usize, usize)>,
     pub mouse_down: bool,
-    pub val: bool,
+    pub val: f64,
+    #[prop_or_default]
+    pub overlay: f64,
 }
 
 #[function_component(GridCell)]
@@ -23,15 +25,20 @@ pub fn grid_cell(props: &GridCellProps) -> Html {
             })
         }
     };
-    fn color(val: bool) -> &'static str {
-        if val {
-            "background-color: black"
+    // positive overlay values tint the cell red and negative ones blue
+    fn color(val: f64, overlay: f64) -> String {
+        let shade = (1.0 - val.clamp(0.0, 1.0)) * 255.0;
+        let alpha = overlay.abs().clamp(0.0, 1.0) * 0.8;
+        let mix = |target: f64| (shade * (1.0 - alpha) + target * alpha).round() as u8;
+        let (r, g, b) = if overlay >= 0.0 {
+            (mix(255.0), mix(0.0), mix(0.0))
         } else {
-            "background-color: white"
-        }
+            (mix(0.0), mix(0.0), mix(255.0))
+        };
+        format!("background-color: rgb({}, {}, {})", r, g, b)
     }
     html! {
-        <div style={ color(props.val) }
+        <div style={ color(props.val, props.overlay) }
             class="w-4 h-4 border border-gray-400"
             onmouseover={ changemouse }
         >
@@ -39,10 +46,82 @@ pub fn grid_cell(props: &GridCellProps) -> Html {
     }
 }
 
+#[derive(Properties, Clone, PartialEq)]
+pub struct ImagePreviewProps {
+    pub image: Vec<f64>,
+}
+
+#[function_component(ImagePreview)]
+pub fn image_preview(props: &ImagePreviewProps) -> Html {
+    let rows = props
+        .image
+        .chunks(28)
+        .map(|row| {
+            let cells = row
+                .iter()
+                .map(|val| {
+                    let shade = ((1.0 - val.clamp(0.0, 1.0)) * 255.0).round() as u8;
+                    html! {
+                        <div style={ format!("background-color: rgb({}, {}, {})", shade, shade, shade) }
+                             class="w-2 h-2">
+                        </div>
+                    }
+                })
+                .collect::<Html>();
+            html! { <div class="flex flex-row">{ cells }</div> }
+        })
+        .collect::<Html>();
+    html! {
+        <div class="flex flex-col border border-gray-400">
+            { rows }
+        </div>
+    }
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct HiddenUnitsProps {
+    pub values: Vec<f64>,
+}
+
+// one cell per hidden unit, darker the more strongly it fires relative to the strongest
+#[function_component(HiddenUnits)]
+pub fn hidden_units(props: &HiddenUnitsProps) -> Html {
+    let max = props.values.iter().fold(0.0, |a: f64, b| a.max(*b));
+    let rows = props
+        .values
+        .chunks(16)
+        .enumerate()
+        .map(|(i, row)| {
+            let cells = row
+                .iter()
+                .enumerate()
+                .map(|(j, val)| {
+                    let level = if max > 0.0 { val / max } else { 0.0 };
+                    let shade = ((1.0 - level) * 255.0).round() as u8;
+                    html! {
+                        <div style={ format!("background-color: rgb({}, {}, 255)", shade, shade) }
+                             title={ format!("unit {}: {:.3}", i * 16 + j, val) }
+                             class="w-3 h-3 border border-gray-200">
+                        </div>
+                    }
+                })
+                .collect::<Html>();
+            html! { <div class="flex flex-row">{ cells }</div> }
+        })
+        .collect::<Html>();
+    html! {
+        <div class="flex flex-col">
+            { rows }
+        </div>
+    }
+}
+
 #[derive(Properties, Clone, PartialEq)]
 pub struct GridProps {
-    pub grid: Callback<[[bool; 28]; 28]>,
-    pub init_grid: [[bool; 28]; 28],
+    pub grid: Callback<[[f64; 28]; 28]>,
+    pub init_grid: [[f64; 28]; 28],
+    #[prop_or_default]
+    pub overlay: Option<Vec<f64>>,
 }
 
 #[function_component(Grid)]
@@ -57,7 +136,7 @@ pub fn grid(props: &GridProps) -> Html {
         let props = props.clone();
         Callback::from(move |(row, col): (usize, usize)| {
             let mut grid_local = (*grid_local_handler).clone();
-            grid_local[row][col] = true;
+            grid_local[row][col] = 1.0;
             props.grid.emit(grid_local);
             grid_local_handler.set(grid_local);
         })
@@ -79,13 +158,10 @@ pub fn grid(props: &GridProps) -> Html {
                 let sample = get_sample().await;
                 let vec_image = sample
                     .image
-                    .iter()
-                    .map(|&x| x > 0.0)
-                    .collect::<Vec<bool>>()
                     .chunks(28)
                     .map(|x| x.to_vec())
-                    .collect::<Vec<Vec<bool>>>();
-                let mut grid = [[false; 28]; 28];
+                    .collect::<Vec<Vec<f64>>>();
+                let mut grid = [[0.0; 28]; 28];
                 for (i, row) in vec_image.iter().enumerate() {
                     for (j, col) in row.iter().enumerate() {
                         grid[i][j] = *col;
@@ -107,6 +183,7 @@ pub fn grid(props: &GridProps) -> Html {
                           col={col}
                           set_cell={grid_local_modify.clone()}
                           val={grid_local_handler[row][col]}
+                          overlay={props.overlay.as_ref().map_or(0.0, |x| x[row * 28 + col])}
                           mouse_down={mouse_down} />
             });
         }
@@ -137,3 +214,74 @@ pub fn grid(props: &GridProps) -> Html {
         </>
     }
 }
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct WideGridProps {
+    pub image: Callback<Vec<f64>>,
+    pub width: usize,
+    pub height: usize,
+}
+
+// a drawing surface of any size, emitting the image row by row
+#[function_component(WideGrid)]
+pub fn wide_grid(props: &WideGridProps) -> Html {
+    let mouse_down_handle = use_state(|| false);
+    let mouse_down = *mouse_down_handle;
+    let size = props.width * props.height;
+    let image_handle = use_state(|| vec![0.0; size]);
+
+    let modify = {
+        let image_handle = image_handle.clone();
+        let props = props.clone();
+        Callback::from(move |(row, col): (usize, usize)| {
+            let mut image = (*image_handle).clone();
+            image[row * props.width + col] = 1.0;
+            props.image.emit(image.clone());
+            image_handle.set(image);
+        })
+    };
+
+    let clear = {
+        let image_handle = image_handle.clone();
+        let props = props.clone();
+        Callback::from(move |_| {
+            props.image.emit(vec![0.0; size]);
+            image_handle.set(vec![0.0; size]);
+        })
+    };
+
+    let rows = (0..props.height)
+        .map(|row| {
+            let cells = (0..props.width)
+                .map(|col| {
+                    html! {
+                        <GridCell row={row}
+                                  col={col}
+                                  set_cell={modify.clone()}
+                                  val={image_handle[row * props.width + col]}
+                                  mouse_down={mouse_down} />
+                    }
+                })
+                .collect::<Html>();
+            html! { <div class="flex flex-row">{ cells }</div> }
+        })
+        .collect::<Html>();
+
+    html! {
+        <>
+        <div
+            onmousedown={
+                let mouse_down_handle = mouse_down_handle.clone();
+                Callback::from(move |_| mouse_down_handle.set(true))
+            }
+            onmouseup={
+                let mouse_down_handle = mouse_down_handle.clone();
+                Callback::from(move |_| mouse_down_handle.set(false))
+            }
+            class="flex flex-col">
+            { rows }
+        </div>
+        <button class="grid-control" onclick={clear}>{ "Clear Grid" }</button>
+        </>
+    }
+}
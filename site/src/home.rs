@@ -1,23 +1,61 @@
-use crate::{api::{get_weights, send_weights, weights_delete},
+use crate::{api::{get_block, get_federated_version, get_version, get_weights, send_update, weights_delete},
+            generator::Generator,
+            grid::{HiddenUnits, ImagePreview, WideGrid},
             model_agent::{ControlSignal, ModelReactor},
+            weights_view::{ClassWeights, Filters},
             Grid};
 use model::{
-    util,
-    util::Weights,
+    adversarial,
+    expression::{self, ExpressionReading},
+    calibration,
+    dataset::default_classes,
+    federated,
+    history::TrainingHistory,
+    ood::OodDetector,
+    preprocess,
+    replay::{self, Ewc, ReplayBuffer},
+    saliency::{self, Saliency},
+    segment::{self, Reading, Segmentation},
+    util::{argmax, DataSingle, ModelCard, HIDDEN_SIZE, IMAGE_SIDE, IMAGE_SIZE},
     Model,
 };
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::spawn_local;
-use web_sys::{EventTarget, HtmlInputElement};
+use web_sys::{EventTarget, HtmlAnchorElement, HtmlInputElement, HtmlSelectElement};
 use yew::{function_component, html, prelude::*};
 use yew_agent::reactor::{use_reactor_bridge, ReactorEvent};
 
+// the multi-digit canvas fits about five digits
+const NUMBER_WIDTH: usize = IMAGE_SIDE * 5;
+// at most this many replayed examples are mixed into each online step
+const REPLAY_BATCH: usize = 32;
+
 #[function_component(Home)]
 pub fn home() -> Html {
-    let grid_component_handler = use_state(|| [[false; 28]; 28]);
+    let grid_component_handler = use_state(|| [[0.0; 28]; 28]);
     let inference_handler = use_state(|| 0);
+    let confidence_handle = use_state(|| 0.0);
+    let recognised_handle = use_state(|| true);
+    // overrides the rejection threshold in the model card
+    let ood_threshold_handle = use_state(|| None::<f64>);
+    let number_handle = use_state(Vec::<f64>::new);
+    let reading_handle = use_state(|| None::<Reading>);
+    let expression_handle = use_state(|| None::<ExpressionReading>);
+    let segmentation_handle = use_state(Segmentation::default);
+    let expression_mode_handle = use_state(|| false);
     let show_grid_handle = use_state(|| false);
+    let show_preprocessed_handle = use_state(|| false);
+    let show_hidden_handle = use_state(|| false);
+    // the hidden activations of the drawing, kept while the hidden units are shown
+    let embedding_handle = use_state(Vec::<f64>::new);
+    let show_weights_handle = use_state(|| false);
+    let show_generator_handle = use_state(|| false);
+    let saliency_handle = use_state(|| None::<Saliency>);
+    // worked out when the drawing or the method changes, rather than on every render
+    let overlay_handle = use_state(|| None::<Vec<f64>>);
+    let adversarial_handle = use_state(|| None::<(f64, Vec<f64>)>);
     let input_handle = use_state(|| 0);
     let loss_handle = use_state(|| 0.0);
     let block_size_handle = use_state(|| 128);
@@ -30,10 +68,28 @@ pub fn home() -> Html {
     let local_train_toggle = Arc::new(Mutex::new(false));
     let data_caching = use_state(||0);
     let data_cached = use_state(|| 0);
+    // the version local training started from and the samples trained since
+    let base_handle = use_state(|| None::<(u64, Model)>);
+    let samples_handle = use_state(|| 0);
+    let tune_samples_handle = use_state(|| 0);
+    // everything trained in this session, kept across weight resets
+    let history_handle = use_mut_ref(TrainingHistory::new);
+    let card_handle = use_state(|| None::<ModelCard>);
+    // earlier examples mixed into each "Tune Model" step, and the weights tuning should stay near
+    let replay_size_handle = use_state(|| 0);
+    let ewc_lambda_handle = use_state(|| 0.0);
+    let replay_handle = use_mut_ref(|| ReplayBuffer::new(0));
+    let ewc_handle = use_mut_ref(|| None::<Ewc>);
+
+    // bumped whenever the model gets new weights, so the weight view is only exported again then
+    let weights_generation = use_mut_ref(|| 0u64);
+    let weights_view = use_mut_ref(|| None::<(u64, Rc<Vec<Vec<f64>>>, Rc<Vec<Vec<f64>>>)>);
 
     let model_handle = use_state(|| {
-        Model::new(
-            (util::random_dist(784, 128), util::random_dist(128, 10)),
+        Model::random(
+            IMAGE_SIZE,
+            HIDDEN_SIZE,
+            default_classes(10),
             (*learning_rate_handle, *learning_rate_handle),
         )
     });
@@ -45,6 +101,9 @@ pub fn home() -> Html {
     let data_cached_response = data_cached.clone();
     let learning_rate_handle_response = learning_rate_handle.clone();
     let model_handle_response = model_handle.clone();
+    let weights_generation_response = weights_generation.clone();
+    let samples_handle_response = samples_handle.clone();
+    let history_handle_response = history_handle.clone();
 
     let block_size_handle_model = block_size_handle.clone();
 
@@ -57,8 +116,21 @@ pub fn home() -> Html {
             data_caching_response.set(status.data_futures_len);
             block_size_handle_model.set(status.batch_size);
             learning_rate_handle_response.set(status.lrate);
-            model_handle_response.set(Model::new(
-                status.weights.weights,
+            samples_handle_response.set(status.samples);
+            let mut history = history_handle_response.borrow_mut();
+            if let Some(step) = status.step {
+                if history.last_step().map_or(true, |x| x.step < step.step) {
+                    history.steps.push(step);
+                }
+            }
+            if let Some(epoch) = status.epoch {
+                if history.epochs.last().map_or(true, |x| x.epoch < epoch.epoch) {
+                    history.epochs.push(epoch);
+                }
+            }
+            *weights_generation_response.borrow_mut() += 1;
+            model_handle_response.set(Model::from_weights(
+                status.weights,
                 (*learning_rate_handle_response, *learning_rate_handle_response),
             ));
         }
@@ -68,30 +140,57 @@ pub fn home() -> Html {
 
     let infer_callback = {
         let inference_handler = inference_handler.clone();
+        let confidence_handle = confidence_handle.clone();
         let model = model_handle.clone();
-        Callback::from(move |grid: [[bool; 28]; 28]| {
+        let card_handle = card_handle.clone();
+        let recognised_handle = recognised_handle.clone();
+        let ood_threshold_handle = ood_threshold_handle.clone();
+        let saliency_handle = saliency_handle.clone();
+        let overlay_handle = overlay_handle.clone();
+        let show_hidden_handle = show_hidden_handle.clone();
+        let embedding_handle = embedding_handle.clone();
+        Callback::from(move |grid: [[f64; 28]; 28]| {
             let inference_handler = inference_handler.clone();
+            let confidence_handle = confidence_handle.clone();
+            let recognised_handle = recognised_handle.clone();
+            let overlay_handle = overlay_handle.clone();
+            let embedding_handle = embedding_handle.clone();
+            let saliency = *saliency_handle;
+            let show_hidden = *show_hidden_handle;
             let model = model.clone();
+            // the temperature and detector fitted by the API for the loaded weights
+            let temperature = card_handle.as_ref().and_then(|x| x.temperature).unwrap_or(1.0);
+            let ood = card_detector(&model, card_handle.as_ref())
+                .cloned()
+                .map(|mut ood| {
+                    ood.threshold = ood_threshold_handle.unwrap_or(ood.threshold);
+                    ood
+                });
             spawn_local(async move {
-                let grid_infer = grid
-                    .iter()
-                    .flatten()
-                    .map(|x| if *x { 1.0 } else { 0.0 })
-                    .collect::<Vec<f64>>();
-                inference_handler.set(model.infer1d(grid_infer));
+                let grid_infer = preprocess_grid(grid);
+                if show_hidden {
+                    embedding_handle.set(model.embed(grid_infer.clone()));
+                }
+                let recognised = ood.is_none_or(|ood| ood.recognised(&model, vec![grid_infer.clone()])[0]);
+                let probabilities = calibration::probabilities(&model, vec![grid_infer], temperature).remove(0);
+                let prediction = argmax(probabilities.iter());
+                inference_handler.set(prediction);
+                confidence_handle.set(probabilities[prediction as usize]);
+                recognised_handle.set(recognised);
+                overlay_handle.set(saliency.map(|method| saliency_overlay(&model, grid, prediction, method)));
             });
         })
     };
 
     let grid_callback = {
         let grid_component_handler = grid_component_handler.clone();
-        Callback::from(move |grid: [[bool; 28]; 28]| {
+        Callback::from(move |grid: [[f64; 28]; 28]| {
             grid_component_handler.set(grid);
         })
     };
 
     let mod_callback = {
-        Callback::from(move |grid: [[bool; 28]; 28]| {
+        Callback::from(move |grid: [[f64; 28]; 28]| {
             grid_callback.emit(grid);
             infer_callback.emit(grid);
         })
@@ -104,12 +203,134 @@ pub fn home() -> Html {
         })
     };
 
-    fn print_grid(grid: [[bool; 28]; 28]) -> String {
+    let show_preprocessed_callback = {
+        let show_preprocessed_handle = show_preprocessed_handle.clone();
+        Callback::from(move |_| {
+            show_preprocessed_handle.set(!*show_preprocessed_handle);
+        })
+    };
+
+    let show_hidden_callback = {
+        let show_hidden_handle = show_hidden_handle.clone();
+        let embedding_handle = embedding_handle.clone();
+        let model_handle = model_handle.clone();
+        let grid_component_handler = grid_component_handler.clone();
+        Callback::from(move |_| {
+            if !*show_hidden_handle {
+                embedding_handle.set(model_handle.embed(preprocess_grid(*grid_component_handler)));
+            }
+            show_hidden_handle.set(!*show_hidden_handle);
+        })
+    };
+
+    let show_weights_callback = {
+        let show_weights_handle = show_weights_handle.clone();
+        Callback::from(move |_| {
+            show_weights_handle.set(!*show_weights_handle);
+        })
+    };
+
+    let show_generator_callback = {
+        let show_generator_handle = show_generator_handle.clone();
+        Callback::from(move |_| {
+            show_generator_handle.set(!*show_generator_handle);
+        })
+    };
+
+    let flip_callback = {
+        let adversarial_handle = adversarial_handle.clone();
+        let grid_component_handler = grid_component_handler.clone();
+        let model_handle = model_handle.clone();
+        Callback::from(move |_| {
+            let image = preprocess_grid(*grid_component_handler);
+            let res = adversarial::flip(&model_handle, &image, &[0.02, 0.05, 0.1, 0.15, 0.2, 0.3]);
+            if res.is_none() {
+                web_sys::window()
+                    .unwrap()
+                    .alert_with_message("Could not flip the prediction within 0.3")
+                    .unwrap();
+            }
+            adversarial_handle.set(res);
+        })
+    };
+
+    let saliency_callback = {
+        let saliency_handle = saliency_handle.clone();
+        let overlay_handle = overlay_handle.clone();
+        let model_handle = model_handle.clone();
+        let grid_component_handler = grid_component_handler.clone();
+        let inference_handler = inference_handler.clone();
+        Callback::from(move |e: Event| {
+            let target: Option<EventTarget> = e.target();
+            let input = target.and_then(|t| t.dyn_into::<HtmlSelectElement>().ok());
+            if let Some(input) = input {
+                let method = match input.value().as_str() {
+                    "gradient" => Some(Saliency::Gradient),
+                    "smooth_grad" => Some(Saliency::SmoothGrad),
+                    "integrated_gradients" => Some(Saliency::IntegratedGradients),
+                    _ => None,
+                };
+                saliency_handle.set(method);
+                overlay_handle.set(method.map(|method| {
+                    saliency_overlay(&model_handle, *grid_component_handler, *inference_handler, method)
+                }));
+            }
+        })
+    };
+
+    // saliency of the predicted class, carried back from the preprocessed image onto the drawing
+    fn saliency_overlay(model: &Model, grid: [[f64; 28]; 28], class: u8, method: Saliency) -> Vec<f64> {
+        let image = grid.iter().flatten().copied().collect::<Vec<f64>>();
+        let map = saliency::saliency(model, &preprocess_grid(grid), class, method);
+        saliency::normalize(&preprocess::unstyle(&map, &image, IMAGE_SIDE))
+    }
+
+    // the card's detector, unless it was fitted to a model of another shape
+    fn card_detector<'a>(model: &Model, card: Option<&'a ModelCard>) -> Option<&'a OodDetector> {
+        card.and_then(|x| x.ood.as_ref())
+            .filter(|ood| ood.fits(model.weights.0.ncols(), model.num_classes()))
+    }
+
+    // the digits on the wide canvas, read with the loaded weights' temperature and detector
+    fn read_number(model: &Model, card: Option<&ModelCard>, image: &[f64], method: Segmentation) -> Reading {
+        let temperature = card.and_then(|x| x.temperature).unwrap_or(1.0);
+        segment::read(model, image, NUMBER_WIDTH, method, temperature, card_detector(model, card))
+    }
+
+    // the wide canvas read as whichever of a number or an expression is shown
+    fn read_canvas(
+        model: &Model,
+        card: Option<&ModelCard>,
+        image: &[f64],
+        method: Segmentation,
+        expression_mode: bool,
+    ) -> (Option<Reading>, Option<ExpressionReading>) {
+        if image.is_empty() {
+            (None, None)
+        } else if expression_mode {
+            let temperature = card.and_then(|x| x.temperature).unwrap_or(1.0);
+            let ood = card_detector(model, card);
+            (None, Some(expression::read(model, image, NUMBER_WIDTH, method, temperature, ood)))
+        } else {
+            (Some(read_number(model, card, image, method)), None)
+        }
+    }
+
+    fn download_link(body: String, mime: &str) -> String {
+        format!("data:{};charset=utf-8,{}", mime, String::from(js_sys::encode_uri_component(&body)))
+    }
+
+    fn preprocess_grid(grid: [[f64; 28]; 28]) -> Vec<f64> {
+        let image = grid.iter().flatten().copied().collect::<Vec<f64>>();
+        preprocess::mnist_style(&image, IMAGE_SIDE, None)
+    }
+
+    fn print_grid(grid: [[f64; 28]; 28]) -> String {
         grid.iter()
             .map(|row| {
                 row.iter()
-                    .map(|col| if *col { "1" } else { "0" })
-                    .collect::<Vec<&str>>()
+                    .map(|col| ((col.clamp(0.0, 1.0) * 9.0).round() as u8).to_string())
+                    .collect::<Vec<String>>()
                     .join("")
             })
             .collect::<Vec<String>>()
@@ -121,21 +342,56 @@ pub fn home() -> Html {
         let grid_component_handler = grid_component_handler.clone();
         let loss_handle = loss_handle.clone();
         let model_handle = model_handle.clone();
+        let weights_generation = weights_generation.clone();
+        let tune_samples_handle = tune_samples_handle.clone();
+        let replay_size_handle = replay_size_handle.clone();
+        let ewc_lambda_handle = ewc_lambda_handle.clone();
+        let replay_handle = replay_handle.clone();
+        let ewc_handle = ewc_handle.clone();
         Callback::from(move |_| {
             let input = (*input_handle).clone();
             let grid = (*grid_component_handler).clone();
             let loss_handle = loss_handle.clone();
             let model_handle = model_handle.clone();
+            let weights_generation = weights_generation.clone();
+            let tune_samples_handle = tune_samples_handle.clone();
+            let replay_size = *replay_size_handle;
+            let ewc_lambda = *ewc_lambda_handle;
+            let replay_handle = replay_handle.clone();
+            let ewc_handle = ewc_handle.clone();
             spawn_local(async move {
                 let mut model = (*model_handle).clone();
-                let grid_train = grid
-                    .iter()
-                    .flatten()
-                    .map(|x| if *x { 1.0 } else { 0.0 })
-                    .collect::<Vec<f64>>();
-                let loss = model.train1d(grid_train, input);
+                let grid_train = preprocess_grid(grid);
+                let loss = if replay_size > 0 {
+                    // seed the buffer with a sample of MNIST the first time, or again when its size changes
+                    if replay_handle.borrow().capacity != replay_size || replay_handle.borrow().is_empty() {
+                        let data = get_block(replay_size).await;
+                        let mut buffer = ReplayBuffer::new(replay_size);
+                        buffer.extend_with(&data.data, &mut rand::thread_rng());
+                        *replay_handle.borrow_mut() = buffer;
+                    }
+                    let mut ewc = ewc_handle.borrow_mut();
+                    *ewc = match (ewc_lambda > 0.0, ewc.take()) {
+                        (true, Some(ewc)) => Some(Ewc { lambda: ewc_lambda, ..ewc }),
+                        (true, None) => Some(Ewc::new(&model, &replay_handle.borrow().examples, ewc_lambda)),
+                        (false, _) => None,
+                    };
+                    let example = DataSingle { image: grid_train, target: input };
+                    replay::train_online(
+                        &mut model,
+                        &mut replay_handle.borrow_mut(),
+                        &[example],
+                        replay_size.min(REPLAY_BATCH),
+                        ewc.as_ref(),
+                        &mut rand::thread_rng(),
+                    )
+                } else {
+                    model.train1d(grid_train, input)
+                };
                 loss_handle.set(loss);
+                *weights_generation.borrow_mut() += 1;
                 model_handle.set(model);
+                tune_samples_handle.set(*tune_samples_handle + 1);
             });
         })
     };
@@ -151,18 +407,41 @@ pub fn home() -> Html {
         })
     };
 
+    let model_sub_load = model_sub.clone();
     let load_weights_callback = {
         let model_handle = model_handle.clone();
+        let weights_generation = weights_generation.clone();
         let learning_rate_handle = learning_rate_handle.clone();
+        let base_handle = base_handle.clone();
+        let tune_samples_handle = tune_samples_handle.clone();
+        let card_handle = card_handle.clone();
+        let ewc_handle = ewc_handle.clone();
         Callback::from(move |_| {
             let model_handle = model_handle.clone();
+            let weights_generation = weights_generation.clone();
             let learning_rate_handle = learning_rate_handle.clone();
+            let base_handle = base_handle.clone();
+            let ewc_handle = ewc_handle.clone();
+            let tune_samples_handle = tune_samples_handle.clone();
+            let card_handle = card_handle.clone();
+            let model_sub = model_sub_load.clone();
             spawn_local(async move {
-                let weights = get_weights().await;
-                let new_model = Model::new(
-                    weights.weights,
+                let version = get_federated_version().await;
+                let weights = get_version(version).await;
+                if let Err(e) = weights.check_input_size(IMAGE_SIZE) {
+                    web_sys::window().unwrap().alert_with_message(&e).unwrap();
+                    return;
+                }
+                card_handle.set(weights.card.clone());
+                let new_model = Model::from_weights(
+                    weights.clone(),
                     (*learning_rate_handle, *learning_rate_handle),
                 );
+                model_sub.send(ControlSignal::SetWeights(weights));
+                base_handle.set(Some((version, new_model.clone())));
+                tune_samples_handle.set(0);
+                *ewc_handle.borrow_mut() = None;
+                *weights_generation.borrow_mut() += 1;
                 model_handle.set(new_model);
                 web_sys::window()
                     .unwrap()
@@ -174,15 +453,28 @@ pub fn home() -> Html {
 
     let send_weights_callback = {
         let model_handle = model_handle.clone();
+        let base_handle = base_handle.clone();
+        let samples_handle = samples_handle.clone();
+        let tune_samples_handle = tune_samples_handle.clone();
         Callback::from(move |_| {
             let model_handle = model_handle.clone();
+            let base_handle = base_handle.clone();
+            let samples = *samples_handle + *tune_samples_handle;
             spawn_local(async move {
-                let model = (*model_handle).clone();
-                let weights = model.export_weights();
-                send_weights(Weights { weights }).await;
+                let message = match (*base_handle).clone() {
+                    Some((version, base)) => {
+                        let update = federated::make_update(version, &base, &model_handle, samples);
+                        if send_update(update).await {
+                            "Update sent to API"
+                        } else {
+                            "The API has a newer version, reset weights with API and train again"
+                        }
+                    }
+                    None => "Reset weights with API before sending an update",
+                };
                 web_sys::window()
                     .unwrap()
-                    .alert_with_message("Weights sent to API")
+                    .alert_with_message(message)
                     .unwrap();
             });
         })
@@ -190,17 +482,27 @@ pub fn home() -> Html {
 
     let delete_weights_callback = {
         let model_handle = model_handle.clone();
+        let weights_generation = weights_generation.clone();
         let learning_rate_handle = learning_rate_handle.clone();
+        let card_handle = card_handle.clone();
         Callback::from(move |_| {
             let model_handle = model_handle.clone();
+            let weights_generation = weights_generation.clone();
             let learning_rate_handle = learning_rate_handle.clone();
+            let card_handle = card_handle.clone();
             spawn_local(async move {
                 weights_delete().await;
                 let weights = get_weights().await;
-                let new_model = Model::new(
-                    weights.weights,
+                if let Err(e) = weights.check_input_size(IMAGE_SIZE) {
+                    web_sys::window().unwrap().alert_with_message(&e).unwrap();
+                    return;
+                }
+                card_handle.set(weights.card.clone());
+                let new_model = Model::from_weights(
+                    weights,
                     (*learning_rate_handle, *learning_rate_handle),
                 );
+                *weights_generation.borrow_mut() += 1;
                 model_handle.set(new_model);
                 web_sys::window()
                     .unwrap()
@@ -221,6 +523,115 @@ pub fn home() -> Html {
         })
     };
 
+    let ood_threshold_callback = {
+        let ood_threshold_handle = ood_threshold_handle.clone();
+        Callback::from(move |e: Event| {
+            let target: Option<EventTarget> = e.target();
+            let input = target.and_then(|t| t.dyn_into::<HtmlInputElement>().ok());
+            if let Some(input) = input {
+                ood_threshold_handle.set(input.value().parse::<f64>().ok());
+            }
+        })
+    };
+
+    let number_callback = {
+        let number_handle = number_handle.clone();
+        let reading_handle = reading_handle.clone();
+        let model_handle = model_handle.clone();
+        let card_handle = card_handle.clone();
+        let segmentation_handle = segmentation_handle.clone();
+        let expression_handle = expression_handle.clone();
+        let expression_mode_handle = expression_mode_handle.clone();
+        Callback::from(move |image: Vec<f64>| {
+            let (reading, expression) = read_canvas(
+                &model_handle,
+                card_handle.as_ref(),
+                &image,
+                *segmentation_handle,
+                *expression_mode_handle,
+            );
+            reading_handle.set(reading);
+            expression_handle.set(expression);
+            number_handle.set(image);
+        })
+    };
+
+    let segmentation_callback = {
+        let segmentation_handle = segmentation_handle.clone();
+        let number_handle = number_handle.clone();
+        let reading_handle = reading_handle.clone();
+        let model_handle = model_handle.clone();
+        let card_handle = card_handle.clone();
+        let expression_handle = expression_handle.clone();
+        let expression_mode_handle = expression_mode_handle.clone();
+        Callback::from(move |e: Event| {
+            let target: Option<EventTarget> = e.target();
+            let input = target.and_then(|t| t.dyn_into::<HtmlSelectElement>().ok());
+            if let Some(input) = input {
+                let method = Segmentation::from_name(&input.value()).unwrap_or_default();
+                segmentation_handle.set(method);
+                let (reading, expression) = read_canvas(
+                    &model_handle,
+                    card_handle.as_ref(),
+                    &number_handle,
+                    method,
+                    *expression_mode_handle,
+                );
+                reading_handle.set(reading);
+                expression_handle.set(expression);
+            }
+        })
+    };
+
+    let expression_mode_callback = {
+        let expression_mode_handle = expression_mode_handle.clone();
+        let number_handle = number_handle.clone();
+        let reading_handle = reading_handle.clone();
+        let expression_handle = expression_handle.clone();
+        let model_handle = model_handle.clone();
+        let card_handle = card_handle.clone();
+        let segmentation_handle = segmentation_handle.clone();
+        Callback::from(move |e: Event| {
+            let target: Option<EventTarget> = e.target();
+            let input = target.and_then(|t| t.dyn_into::<HtmlSelectElement>().ok());
+            if let Some(input) = input {
+                let expression_mode = input.value() == "expression";
+                expression_mode_handle.set(expression_mode);
+                let (reading, expression) = read_canvas(
+                    &model_handle,
+                    card_handle.as_ref(),
+                    &number_handle,
+                    *segmentation_handle,
+                    expression_mode,
+                );
+                reading_handle.set(reading);
+                expression_handle.set(expression);
+            }
+        })
+    };
+
+    let replay_size_callback = {
+        let replay_size_handle = replay_size_handle.clone();
+        Callback::from(move |e: Event| {
+            let target: Option<EventTarget> = e.target();
+            let input = target.and_then(|t| t.dyn_into::<HtmlInputElement>().ok());
+            if let Some(input) = input {
+                replay_size_handle.set(input.value().parse::<usize>().unwrap_or(0));
+            }
+        })
+    };
+
+    let ewc_lambda_callback = {
+        let ewc_lambda_handle = ewc_lambda_handle.clone();
+        Callback::from(move |e: Event| {
+            let target: Option<EventTarget> = e.target();
+            let input = target.and_then(|t| t.dyn_into::<HtmlInputElement>().ok());
+            if let Some(input) = input {
+                ewc_lambda_handle.set(input.value().parse::<f64>().unwrap_or(0.0));
+            }
+        })
+    };
+
     let cache_size_callback = {
         let cache_size_handle = cache_size_handle.clone();
         Callback::from(move |e: Event| {
@@ -271,6 +682,22 @@ pub fn home() -> Html {
         })
     };
 
+    // the history is only serialised when a download link is clicked, not on every render
+    let history_download_callback = |json: bool| {
+        let history_handle = history_handle.clone();
+        Callback::from(move |e: MouseEvent| {
+            let history = history_handle.borrow();
+            let href = match json {
+                true => download_link(serde_json::to_string(&*history).unwrap(), "application/json"),
+                false => download_link(history.steps_csv(), "text/csv"),
+            };
+            let anchor = e.current_target().and_then(|t| t.dyn_into::<HtmlAnchorElement>().ok());
+            if let Some(anchor) = anchor {
+                anchor.set_href(&href);
+            }
+        })
+    };
+
     html! {
         <>
             <div>
@@ -284,12 +711,94 @@ pub fn home() -> Html {
                 <div id="wrapper">
                     <div id="left">
                         <Grid grid={ mod_callback }
-                              init_grid={ [[false; 28]; 28] }/>
+                              init_grid={ [[0.0; 28]; 28] }
+                              overlay={ (*overlay_handle).clone() }/>
                         <button class="grid-control" onclick={ show_grid_callback }>{ "Show Data" }</button>
+                        <button class="grid-control" onclick={ show_preprocessed_callback }>{ "Show Preprocessed" }</button>
+                        <button class="grid-control" onclick={ show_hidden_callback }>{ "Show Hidden Units" }</button>
+                        <button class="grid-control" onclick={ flip_callback }>{ "Flip Prediction" }</button>
+                        <select class="grid-control" onchange={ saliency_callback }>
+                            <option value="none" selected=true>{ "No Saliency" }</option>
+                            <option value="gradient">{ "Gradient" }</option>
+                            <option value="smooth_grad">{ "SmoothGrad" }</option>
+                            <option value="integrated_gradients">{ "Integrated Gradients" }</option>
+                        </select>
                         <div> {
                             if *show_grid_handle { print_grid(*grid_component_handler) }
                             else { "".to_string() }
                         } </div>
+                        {
+                            if *show_preprocessed_handle {
+                                html! { <ImagePreview image={ preprocess_grid(*grid_component_handler) } /> }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        {
+                            if *show_hidden_handle {
+                                html! { <HiddenUnits values={ (*embedding_handle).clone() } /> }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        <button class="grid-control" onclick={ show_generator_callback }>{ "Show Generator" }</button>
+                        {
+                            if *show_generator_handle {
+                                html! { <Generator drawing={ preprocess_grid(*grid_component_handler) } /> }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        <div id="number">
+                            <WideGrid image={ number_callback } width={ NUMBER_WIDTH } height={ IMAGE_SIDE } />
+                            <select class="grid-control" onchange={ segmentation_callback }>
+                                <option value="connected_components" selected=true>{ "Connected Components" }</option>
+                                <option value="column_projection">{ "Column Projection" }</option>
+                            </select>
+                            <select class="grid-control" onchange={ expression_mode_callback }>
+                                <option value="number" selected=true>{ "Number" }</option>
+                                <option value="expression">{ "Expression" }</option>
+                            </select>
+                            {
+                                if let Some(res) = (*expression_handle).clone() {
+                                    html! {
+                                        <div>
+                                            <p id="expression-text">{ format!("Expression: {}", res.reading.text) }</p>
+                                            <p id="expression-result">{
+                                                match (res.evaluation, res.error) {
+                                                    (Some(evaluation), _) => match evaluation.correct {
+                                                        Some(correct) => format!("= {} ({})", evaluation.value, if correct { "correct" } else { "incorrect" }),
+                                                        None => format!("= {}", evaluation.value),
+                                                    },
+                                                    (None, Some(error)) => format!("Could not evaluate: {}", error),
+                                                    (None, None) => String::new(),
+                                                }
+                                            }</p>
+                                        </div>
+                                    }
+                                } else if let Some(reading) = (*reading_handle).as_ref() {
+                                    html! {
+                                        <div>
+                                            <p id="number-text">{ format!("Number: {}", reading.text) }</p>
+                                            <p>{ reading.digits.iter().map(|x| format!("{} ({:.1}%)", x.class, x.confidence * 100.0)).collect::<Vec<String>>().join(" ") }</p>
+                                        </div>
+                                    }
+                                } else {
+                                    html! {}
+                                }
+                            }
+                        </div>
+                        {
+                            match (*adversarial_handle).clone() {
+                                Some((epsilon, image)) => html! {
+                                    <div>
+                                        <p>{ format!("Perturbed by at most {} per pixel: {}", epsilon, model_handle.class_name(model_handle.infer1d(image.clone()))) }</p>
+                                        <ImagePreview image={ image } />
+                                    </div>
+                                },
+                                None => html! {},
+                            }
+                        }
                     </div>
                     <div id="right">
                         <div id="weights">
@@ -300,7 +809,7 @@ pub fn home() -> Html {
                             </div>
                             <div>
                                 <button onclick={ send_weights_callback }>
-                                    { "Send weights to API" }
+                                    { "Send update to API" }
                                 </button>
                             </div>
                             <div>
@@ -309,8 +818,36 @@ pub fn home() -> Html {
                                 </button>
                             </div>
                         </div>
+                        {
+                            match &*card_handle {
+                                Some(card) => html! {
+                                    <div id="card">
+                                        <p>{ format!("Layers: {:?} ({})", card.layers, card.activations.join(", ")) }</p>
+                                        <p>{ format!("Input: {}x{}, {}", card.input_shape.0, card.input_shape.1, card.preprocessing.join(", ")) }</p>
+                                        <p>{ format!("Dataset: {} {}", card.dataset.clone().unwrap_or_default(), card.dataset_hash.clone().unwrap_or_default()) }</p>
+                                        <p>{ format!("Hyperparameters: {}", card.hyperparameters.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<String>>().join(", ")) }</p>
+                                        <p>{ format!("Settings: {}", card.settings.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<String>>().join(", ")) }</p>
+                                        <p>{ format!("Accuracy: {}", card.accuracy.map_or("unknown".to_string(), |x| format!("{:.4}", x))) }</p>
+                                        <p>{ format!("Temperature: {}", card.temperature.map_or("uncalibrated".to_string(), |x| format!("{:.3}", x))) }</p>
+                                        <p>{ format!("Rejection: {}", card.ood.as_ref().map_or("off".to_string(), |x| format!("{:?} above {:.4}", x.method, x.threshold))) }</p>
+                                    </div>
+                                },
+                                None => html! {},
+                            }
+                        }
                         <div>
-                            <p id="inference">{ format!("Inference: {}", *inference_handler) }</p>
+                            <p id="inference">{
+                                match *recognised_handle {
+                                    true => format!("Inference: {} ({:.1}%)", model_handle.class_name(*inference_handler), *confidence_handle * 100.0),
+                                    false => "Inference: unrecognised".to_string(),
+                                }
+                            }</p>
+                            <input onchange={ ood_threshold_callback }
+                                   type="number"
+                                   id="ood-threshold"
+                                   name="ood-threshold"
+                                   step="0.01"
+                                   placeholder="rejection threshold" />
                         </div>
                         <div >
                             <button id="tune" onclick={ tune_callback }>{ "Tune Model" }</button>
@@ -320,9 +857,24 @@ pub fn home() -> Html {
                                        id="target"
                                        name="target"
                                        min="0"
-                                       max="9"
+                                       max={ (model_handle.num_classes() - 1).to_string() }
                                        placeholder="0" />
+                                <p id="label">{ format!("Label: {}", model_handle.class_name(*input_handle)) }</p>
                                 <p id="loss">{ format!("Loss: {}", *loss_handle) }</p>
+                                <input onchange={ replay_size_callback }
+                                       type="number"
+                                       id="replay"
+                                       name="replay"
+                                       min="0"
+                                       max="1024"
+                                       placeholder="replay buffer size (0 is off)" />
+                                <input onchange={ ewc_lambda_callback }
+                                       type="number"
+                                       id="ewc"
+                                       name="ewc"
+                                       min="0.0"
+                                       step="0.1"
+                                       placeholder="EWC lambda (0 is off)" />
                             </div>
                         </div>
                         <div>
@@ -360,6 +912,36 @@ pub fn home() -> Html {
                                 <p id="cached">{ format!("Caching: {}", *data_caching) }</p>
                                 <p id="cached">{ format!("Cached: {}", *data_cached) }</p>
                             </div>
+                            <div>
+                                <a href="#"
+                                   onclick={ history_download_callback(false) }
+                                   download="history.csv">{ "Download History (CSV)" }</a>
+                                <a href="#"
+                                   onclick={ history_download_callback(true) }
+                                   download="history.json">{ "Download History (JSON)" }</a>
+                            </div>
+                        </div>
+                        <div>
+                            <button onclick={ show_weights_callback }>{ "Show Weights" }</button>
+                            {
+                                if *show_weights_handle {
+                                    let generation = *weights_generation.borrow();
+                                    let mut view = weights_view.borrow_mut();
+                                    if view.as_ref().is_none_or(|x| x.0 != generation) {
+                                        let (filters, classes) = model_handle.export_weights();
+                                        *view = Some((generation, Rc::new(filters), Rc::new(classes)));
+                                    }
+                                    let (_, filters, classes) = view.clone().unwrap();
+                                    html! {
+                                        <div>
+                                            <Filters weights={ filters } />
+                                            <ClassWeights weights={ classes } classes={ model_handle.classes.clone() } />
+                                        </div>
+                                    }
+                                } else {
+                                    html! {}
+                                }
+                            }
                         </div>
                     </div>
                 </div>
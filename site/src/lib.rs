@@ -3,8 +3,10 @@ use yew::prelude::*;
 use yew_agent::reactor::ReactorProvider;
 
 pub mod api;
+pub mod generator;
 pub mod grid;
 pub mod home;
+pub mod weights_view;
 
 use grid::Grid;
 use home::Home;
@@ -1,6 +1,8 @@
 use futures::{FutureExt, SinkExt, StreamExt};
 use model::{
-    util::{random_dist, train_handler_wrapper, Data, Weights},
+    dataset::default_classes,
+    history::{Epoch, Step, TrainingHistory},
+    util::{train_handler_wrapper, Data, Weights, HIDDEN_SIZE, IMAGE_SIZE},
     Model,
 };
 use serde::{Deserialize, Serialize};
@@ -36,6 +38,9 @@ pub struct ResponseSignal {
     pub data_futures_len: usize,
     pub iteration: usize,
     pub cache_size: usize,
+    pub samples: usize,
+    pub step: Option<Step>,
+    pub epoch: Option<Epoch>,
 }
 
 pub struct ModelData {
@@ -49,6 +54,10 @@ pub struct ModelData {
     acc: f64,
     iteration: usize,
     cache_size: usize,
+    samples: usize,
+    history: TrainingHistory,
+    // milliseconds since the epoch when the worker started
+    started: f64,
     model: Model,
     send_status: bool,
 
@@ -67,10 +76,10 @@ impl ModelData {
             acc: 0.0,
             iteration: 0,
             cache_size: 5,
-            model: Model::new(
-                (random_dist(784, 128), random_dist(128, 10)),
-                (0.01, 0.01),
-            ),
+            samples: 0,
+            history: TrainingHistory::new(),
+            started: js_sys::Date::now(),
+            model: Model::random(IMAGE_SIZE, HIDDEN_SIZE, default_classes(10), (0.01, 0.01)),
             send_status: true,
         }
     }
@@ -84,7 +93,7 @@ impl ModelData {
 
     fn respond(&mut self) -> ResponseSignal {
         ResponseSignal {
-            weights: Weights{ weights: self.model.export_weights()},
+            weights: self.model.export(),
             loss: self.loss,
             acc: self.acc,
             batch_size: self.batch_size,
@@ -93,6 +102,9 @@ impl ModelData {
             data_futures_len: (self.data_given - self.data_taken) as usize,
             iteration: self.iteration,
             cache_size: self.cache_size,
+            samples: self.samples,
+            step: self.history.last_step().cloned(),
+            epoch: self.history.epochs.last().cloned(),
         }
     }
 
@@ -108,6 +120,8 @@ impl ModelData {
             self.loss = loss;
             self.acc = acc;
             self.iteration += 1;
+            self.samples += self.batch_size;
+            self.history.record_step(loss, acc, self.lrate, self.elapsed());
             self.send_status = true;
         }
     }
@@ -133,15 +147,25 @@ impl ModelData {
         self.send_status = status;
     }
 
+    fn elapsed(&self) -> f64 {
+        (js_sys::Date::now() - self.started) / 1000.0
+    }
+
+    // each run of local training between start and stop is an epoch
     fn set_training(&mut self, status: bool) {
         self.training = status;
         if status {
             self.iteration = 0;
+        } else {
+            self.history.end_epoch(self.elapsed());
+            self.send_status = true;
         }
     }
 
     fn set_weights(&mut self, weights: Weights) {
-        self.model = Model::new(weights.weights, (self.lrate, self.lrate));
+        self.model = Model::from_weights(weights, (self.lrate, self.lrate));
+        self.samples = 0;
+        self.send_status = true;
     }
 
     fn set_batch_size(&mut self, batch_size: usize) {
@@ -150,7 +174,7 @@ impl ModelData {
 
     fn set_learning_rate(&mut self, lrate: f64) {
         self.lrate = lrate;
-        self.model = Model::new(self.model.export_weights(), (lrate, lrate));
+        self.model = Model::from_weights(self.model.export(), (lrate, lrate));
     }
 
     fn set_cache_size(&mut self, cache_size: usize) {
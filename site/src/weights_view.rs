@@ -0,0 +1,119 @@
+use std::rc::Rc;
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
+use yew::prelude::*;
+
+const COLUMNS: usize = 16;
+
+// blue for negative, white for zero and red for positive values in [-1, 1]
+fn diverging(value: f64) -> [u8; 3] {
+    let value = value.clamp(-1.0, 1.0);
+    let fade = ((1.0 - value.abs()) * 255.0).round() as u8;
+    if value >= 0.0 {
+        [255, fade, fade]
+    } else {
+        [fade, fade, 255]
+    }
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct FiltersProps {
+    // the first layer as exported, one row per input pixel and one column per hidden unit
+    pub weights: Rc<Vec<Vec<f64>>>,
+}
+
+// every hidden unit's incoming weights as a small image, each scaled by its own largest weight
+#[function_component(Filters)]
+pub fn filters(props: &FiltersProps) -> Html {
+    let canvas = use_node_ref();
+    {
+        let canvas = canvas.clone();
+        let weights = props.weights.clone();
+        use_effect(move || {
+            if let Some(canvas) = canvas.cast::<HtmlCanvasElement>() {
+                draw_filters(&canvas, &weights);
+            }
+            || ()
+        });
+    }
+    html! {
+        <canvas ref={ canvas } style="width: 100%; image-rendering: pixelated;" />
+    }
+}
+
+fn draw_filters(canvas: &HtmlCanvasElement, weights: &[Vec<f64>]) {
+    let side = (weights.len() as f64).sqrt() as usize;
+    let cell = side + 1;
+    let hidden = weights.first().map_or(0, |x| x.len());
+    let (width, height) = (COLUMNS * cell, (hidden + COLUMNS - 1) / COLUMNS * cell);
+    let mut pixels = vec![255; width * height * 4];
+    for unit in 0..hidden {
+        let max = weights.iter().fold(0.0, |a: f64, x| a.max(x[unit].abs()));
+        let (top, left) = (unit / COLUMNS * cell, unit % COLUMNS * cell);
+        for (i, row) in weights.iter().enumerate().take(side * side) {
+            let value = if max > 0.0 { row[unit] / max } else { 0.0 };
+            let idx = ((top + i / side) * width + left + i % side) * 4;
+            pixels[idx..idx + 3].copy_from_slice(&diverging(value));
+        }
+    }
+    canvas.set_width(width as u32);
+    canvas.set_height(height as u32);
+    let context = canvas
+        .get_context("2d")
+        .unwrap()
+        .unwrap()
+        .dyn_into::<CanvasRenderingContext2d>()
+        .unwrap();
+    let image =
+        ImageData::new_with_u8_clamped_array_and_sh(Clamped(&pixels), width as u32, height as u32)
+            .unwrap();
+    context.put_image_data(&image, 0.0, 0.0).unwrap();
+}
+
+#[derive(Properties, Clone, PartialEq)]
+pub struct ClassWeightsProps {
+    // the second layer as exported, one row per hidden unit and one column per class
+    pub weights: Rc<Vec<Vec<f64>>>,
+    pub classes: Vec<String>,
+}
+
+// the size of each hidden unit's weight into every class, with the overall norm per class
+#[function_component(ClassWeights)]
+pub fn class_weights(props: &ClassWeightsProps) -> Html {
+    let max = props
+        .weights
+        .iter()
+        .flatten()
+        .fold(0.0, |a: f64, b| a.max(b.abs()));
+    let rows = props
+        .classes
+        .iter()
+        .enumerate()
+        .map(|(class, name)| {
+            let column = props.weights.iter().map(|x| x[class]).collect::<Vec<f64>>();
+            let norm = column.iter().map(|x| x * x).sum::<f64>().sqrt();
+            let cells = column
+                .iter()
+                .map(|val| {
+                    let [r, g, b] = diverging(if max > 0.0 { val.abs() / max } else { 0.0 });
+                    html! {
+                        <div style={ format!("background-color: rgb({}, {}, {})", r, g, b) }
+                             class="w-1 h-3">
+                        </div>
+                    }
+                })
+                .collect::<Html>();
+            html! {
+                <div class="flex flex-row items-center">
+                    <p class="w-24">{ format!("{} ({:.2})", name, norm) }</p>
+                    { cells }
+                </div>
+            }
+        })
+        .collect::<Html>();
+    html! {
+        <div class="flex flex-col">
+            { rows }
+        </div>
+    }
+}